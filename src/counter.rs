@@ -15,10 +15,6 @@ impl<K> Counter<K>
         self.with_delta(k, 1);
     }
 
-    pub fn dec(&mut self, k: &K) {
-        self.with_delta(k, -1);
-    }
-
     fn with_delta(&mut self, k: &K, delta: i32) {
         match self.0.get_mut(k) {
             Some(r) => *r += delta,
@@ -28,15 +24,11 @@ impl<K> Counter<K>
         }
     }
 
-    pub fn get(&self, k: &K) -> Option<i32> {
-        self.0.get(k).copied()
+    pub fn remove(&mut self, k: &K) -> Option<i32> {
+        self.0.remove(k)
     }
 
-    pub fn iter(&self) -> std::collections::hash_map::Iter<K, i32> {
+    pub fn iter(&self) -> std::collections::hash_map::Iter<'_, K, i32> {
         self.0.iter()
     }
-
-    pub fn clear(&mut self) {
-        self.0.clear();
-    }
 }
\ No newline at end of file
@@ -1,141 +1,1094 @@
-use std::collections::VecDeque;
-use std::fs;
-use std::fs::DirEntry;
-use std::path::PathBuf;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::{fs, io};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
 use crossbeam_channel::{Receiver, Sender};
+use crossbeam_deque::{Injector, Stealer, Worker};
 use smallvec::{SmallVec, smallvec};
-use crate::{FileRenderItem, RenderItem, RenderType};
+use crate::{DirRenderItem, ErrorRenderItem, FileRenderItem, RenderItem, RenderType, SkippedRenderIten};
+use crate::counter::Counter;
+use crate::git::{RepoInfo, is_repo_root};
+use crate::ignores::IgnoreRules;
+use crate::metadata::{EntryMeta, get_size, get_tree_size};
 use crate::renderer::Renderer;
-use crate::settings::Config;
+use crate::settings::{Config, GroupMode, SortMode};
+use crate::sorting::{compare_extension, compare_natural};
 
+// File batches are the bulk of the events, keeping a few files inline saves an allocation for most of them
+#[allow(clippy::large_enum_variant)]
 pub enum IOEvent {
     FilesListed(FilesInfo),
-    DirectoryStarted(DirectoryInfo)
+    DirectoryStarted(DirectoryInfo),
+    DirectoryFinished(FinishedInfo),
+    ReadFailed(ErrorInfo)
 }
 
 pub struct FileInfo {
-    pub path: PathBuf
+    pub path: PathBuf,
+    pub link: Option<LinkInfo>,
+    /// Only set with `--contains`
+    pub matches_count: Option<usize>,
+    /// Only read when showing columns
+    pub meta: Option<EntryMeta>
+}
+
+pub struct LinkInfo {
+    pub target: PathBuf,
+    pub is_broken: bool
 }
 
 pub struct FilesInfo {
     files: SmallVec<[FileInfo; 4]>,
-    depth: usize
+    depth: usize,
+    /// Whether nothing follows this batch in the directory
+    is_last: bool,
+    /// Whether this is the first batch of the directory, which leads with the folded extensions
+    is_first: bool,
+    /// The extensions folded over the whole directory, with their files count
+    folded: Arc<Vec<(String, i32)>>
 }
 
 pub struct DirectoryInfo {
     depth: usize,
     is_last: bool,
     is_ignored: bool,
+    is_truncated: bool,
+    /// Whether this directory won't be followed by any of its content
+    is_leaf: bool,
+    /// Set on a followed link pointing to one of its ancestors
+    is_recursive: bool,
+    link: Option<LinkInfo>,
+    /// Only read when showing columns
+    meta: Option<EntryMeta>,
+    /// The size of the whole subtree with `--du`, only set when it isn't traversed
+    size: Option<u64>,
+    /// The file system type of mount points
+    mount: Option<String>,
+    /// Set on nested repositories and submodules
+    repo: Option<RepoInfo>,
     name: PathBuf
 }
 
+/// Sent once all the content of a directory has been listed
+pub struct FinishedInfo {
+    /// The depth of the directory content
+    depth: usize,
+    /// Number of dotfiles left out of the listing
+    hidden_count: usize,
+    /// Number of entries left out by `--exclude` or `--contains`
+    filtered_count: usize,
+    /// Number of entries matched by the ignore files, which aren't mentioned in the tree
+    ignored_count: usize,
+    /// The size of the directory and its whole subtree with `--du`
+    size: Option<u64>
+}
+
+/// An entry that couldn't be read, rendered in place of its content
+pub struct ErrorInfo {
+    depth: usize,
+    is_last: bool,
+    is_dir: bool,
+    path: PathBuf,
+    error: io::Error
+}
+
+/// Identifies a directory regardless of the path used to reach it
+#[cfg(unix)]
+#[derive(PartialEq, Eq)]
+struct FileId(u64, u64);
+
+#[cfg(not(unix))]
+#[derive(PartialEq, Eq)]
+struct FileId(PathBuf);
+
+#[cfg(unix)]
+fn get_file_id(path: &Path) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|m| FileId(m.dev(), m.ino()))
+}
+
+#[cfg(not(unix))]
+fn get_file_id(path: &Path) -> Option<FileId> {
+    fs::canonicalize(path).ok().map(FileId)
+}
+
+#[cfg(unix)]
+fn get_device(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|m| m.dev())
+}
+
+#[cfg(not(unix))]
+fn get_device(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// The directories leading to a job, used to detect cycles when following links
+struct Ancestry {
+    id: Option<FileId>,
+    parent: Option<Arc<Ancestry>>
+}
+
+impl Ancestry {
+    fn new(path: &Path, parent: Option<Arc<Ancestry>>) -> Arc<Self> {
+        Arc::new(Ancestry { id: get_file_id(path), parent })
+    }
+
+    fn contains(&self, id: &FileId) -> bool {
+        let mut ancestry = Some(self);
+        while let Some(a) = ancestry {
+            if a.id.as_ref() == Some(id) {
+                return true;
+            }
+            ancestry = a.parent.as_deref();
+        }
+        false
+    }
+}
+
+/// Puts the idle traversal threads to sleep until jobs are pushed or the read is done
+struct Idle {
+    /// How many jobs were pushed so far, and whether the read is done
+    state: Mutex<(usize, bool)>,
+    condvar: Condvar
+}
+
+impl Idle {
+    fn new() -> Self {
+        Idle { state: Mutex::new((0, false)), condvar: Condvar::new() }
+    }
+
+    /// Taken before looking for a job, so that the jobs pushed in the meantime aren't missed
+    fn get_mark(&self) -> usize {
+        self.state.lock().unwrap().0
+    }
+
+    /// Blocks until jobs are pushed after `mark`, returns false once the read is done
+    fn wait(&self, mark: usize) -> bool {
+        let state = self.condvar
+            .wait_while(self.state.lock().unwrap(), |(pushed, is_done)| *pushed == mark && !*is_done)
+            .unwrap();
+        !state.1
+    }
+
+    fn notify_pushed(&self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        self.state.lock().unwrap().0 += count;
+        for _ in 0..count {
+            self.condvar.notify_one();
+        }
+    }
+
+    fn notify_done(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.condvar.notify_all();
+    }
+}
+
+
+/// A directory read request, processed by one of the traversal threads
+struct ReadJob {
+    path: PathBuf,
+    depth: usize,
+    /// Only tracked when following links
+    ancestry: Option<Arc<Ancestry>>,
+    /// The rules of the parent directories
+    ignores: Option<Arc<IgnoreRules>>,
+    /// The device of the root, only set with `--one-file-system`
+    device: Option<u64>,
+    tx_listing: Sender<io::Result<Listing>>
+}
+
+/// The content of a directory, ready to be sent down the pipeline
+struct Listing {
+    entries: Vec<ListedEntry>,
+    depth: usize,
+    hidden_count: usize,
+    filtered_count: usize,
+    ignored_count: usize,
+    /// With `--du`, the size of the directory and its content, but the traversed subdirectories
+    size: Option<u64>
+}
+
+/// Consecutive files are grouped in a single batch
+#[allow(clippy::large_enum_variant)]
+enum ListedEntry {
+    Files(FilesInfo),
+    Error(ErrorInfo),
+    Directory(SubDirectory)
+}
+
+/// An entry of a directory as it's read, before it's classified
+struct Candidate {
+    path: PathBuf,
+    name: OsString,
+    is_symlink: bool,
+    is_real_dir: bool,
+    /// Only set for the entries of a revision, read from git rather than from the file system
+    meta: Option<EntryMeta>
+}
+
+/// A directory entry, identified but not sorted yet
+struct RawEntry {
+    path: PathBuf,
+    name: OsString,
+    is_dir: bool,
+    link: Option<LinkInfo>,
+    matches_count: Option<usize>,
+    meta: Option<EntryMeta>
+}
+
+struct SubDirectory {
+    info: DirectoryInfo,
+    /// Only set when the directory is traversed
+    rx_listing: Option<Receiver<io::Result<Listing>>>
+}
+
+
+/// Reads the tree rooted at `path` on `--threads` threads and sends its content to `tx_io`.
+///
+/// Sibling subtrees are read concurrently but events are always sent in depth-first order,
+/// so the output doesn't depend on the threads count. `on_read` is called with each directory
+/// right before it's read.
+pub fn list_files(path: &Path, config: &Config, tx_io: &Sender<IOEvent>, on_read: &(dyn Fn(&Path) + Sync)) {
+    let injector: Injector<ReadJob> = Injector::new();
+    let workers: Vec<Worker<ReadJob>> = (0..config.get_threads_count())
+        .map(|_| Worker::new_lifo())
+        .collect();
+    let stealers: Vec<Stealer<ReadJob>> = workers.iter().map(|w| w.stealer()).collect();
+    let idle = Idle::new();
+
+    let (tx_listing, rx_listing) = crossbeam_channel::bounded(1);
+    let ancestry = config.args.follow.then(|| Ancestry::new(path, None));
+    let ignores = IgnoreRules::for_parents(path);
+    let device = config.args.one_file_system.then(|| get_device(path)).flatten();
+    injector.push(ReadJob { path: path.to_path_buf(), depth: 0, ancestry, ignores, device, tx_listing });
+
+    thread::scope(|scope| {
+        for worker in workers {
+            let (injector, stealers, idle) = (&injector, &stealers, &idle);
+            scope.spawn(move || {
+                run_worker(&worker, injector, stealers, idle, config, on_read);
+            });
+        }
+
+        match rx_listing.recv().unwrap() {
+            Ok(listing) => {
+                send_listing(listing, tx_io);
+            }
+            Err(error) => tx_io.send(IOEvent::ReadFailed(ErrorInfo {
+                depth: 0,
+                is_last: true,
+                is_dir: true,
+                path: path.to_path_buf(),
+                error
+            })).unwrap()
+        }
+        idle.notify_done();
+    });
+}
+
+/// Returns the size of the directory with `--du`, its subdirectories being summed up as they're sent
+fn send_listing(listing: Listing, tx_io: &Sender<IOEvent>) -> Option<u64> {
+    let mut size = listing.size;
+    for entry in listing.entries {
+        match entry {
+            ListedEntry::Files(files) => tx_io.send(IOEvent::FilesListed(files)).unwrap(),
+            ListedEntry::Error(error) => tx_io.send(IOEvent::ReadFailed(error)).unwrap(),
+            ListedEntry::Directory(dir) => {
+                let dir_size = send_directory(dir, tx_io);
+                size = size.map(|s| s + dir_size.unwrap_or(0));
+            }
+        }
+    }
+
+    tx_io.send(IOEvent::DirectoryFinished(FinishedInfo {
+        depth: listing.depth,
+        hidden_count: listing.hidden_count,
+        filtered_count: listing.filtered_count,
+        ignored_count: listing.ignored_count,
+        size
+    })).unwrap();
+    size
+}
 
-pub fn list_files(path: &PathBuf, config: &Config, depth: usize, tx_io: &Sender<IOEvent>) {
-    let paths = fs::read_dir(path).unwrap();
-    let mut files: Vec<DirEntry> = Vec::with_capacity(32);
-    let mut dirs: Vec<DirEntry> = Vec::with_capacity(32);
-    for path in paths {
-        let path = path.unwrap();
-        if path.file_type().unwrap().is_dir() {
-            dirs.push(path);
+/// Returns the size of a traversed directory with `--du`, the other ones being already accounted for
+fn send_directory(dir: SubDirectory, tx_io: &Sender<IOEvent>) -> Option<u64> {
+    let Some(rx_listing) = dir.rx_listing else {
+        tx_io.send(IOEvent::DirectoryStarted(dir.info)).unwrap();
+        return None;
+    };
+
+    match rx_listing.recv().unwrap() {
+        Ok(listing) => {
+            tx_io.send(IOEvent::DirectoryStarted(dir.info)).unwrap();
+            send_listing(listing, tx_io)
+        },
+        Err(error) => {
+            tx_io.send(IOEvent::ReadFailed(ErrorInfo {
+                depth: dir.info.depth,
+                is_last: dir.info.is_last,
+                is_dir: true,
+                path: dir.info.name,
+                error
+            })).unwrap();
+            None
+        }
+    }
+}
+
+fn run_worker(worker: &Worker<ReadJob>, injector: &Injector<ReadJob>, stealers: &[Stealer<ReadJob>], idle: &Idle, config: &Config,
+              on_read: &(dyn Fn(&Path) + Sync)) {
+    loop {
+        let mark = idle.get_mark();
+        match find_job(worker, injector, stealers) {
+            Some(job) => {
+                on_read(&job.path);
+                read_listing(job, worker, config);
+                // The subdirectories it pushed can be stolen by the idle workers
+                idle.notify_pushed(worker.len());
+            },
+            None if idle.wait(mark) => {},
+            None => return
+        }
+    }
+}
+
+fn find_job(worker: &Worker<ReadJob>, injector: &Injector<ReadJob>, stealers: &[Stealer<ReadJob>]) -> Option<ReadJob> {
+    worker.pop().or_else(|| {
+        std::iter::repeat_with(|| {
+            injector.steal_batch_and_pop(worker)
+                .or_else(|| stealers.iter().map(|s| s.steal()).collect())
+        })
+        .find(|s| !s.is_retry())
+        .and_then(|s| s.success())
+    })
+}
+
+/// Reads the entries of a directory from the file system, or from git with `--tracked`, `--rev` or
+/// `--rev-diff`, the ones that can't be read being added to `errors`
+fn read_candidates(path: &Path, config: &Config, errors: &mut Vec<(PathBuf, io::Error)>) -> io::Result<Vec<Candidate>> {
+    let mut candidates: Vec<Candidate> = Vec::with_capacity(32);
+
+    if let Some(tree) = &config.tree {
+        for entry in tree.get_entries(path) {
+            let entry_path = path.join(&entry.name);
+            // Files deleted from the working tree are still listed, like git does
+            let metadata = tree.is_checked_out.then(|| fs::symlink_metadata(&entry_path).ok()).flatten();
+            let is_symlink = metadata.as_ref().is_some_and(|m| m.file_type().is_symlink());
+            // Submodules are listed by the index like files
+            let is_real_dir = entry.is_dir || metadata.as_ref().is_some_and(|m| m.is_dir());
+            // The sorting and `--where` can't read the metadata of a revision from the file system
+            let meta = (!tree.is_checked_out).then(|| EntryMeta {
+                size: entry.size.unwrap_or(0),
+                mtime: None,
+                mode: entry.mode,
+                uid: None
+            });
+            candidates.push(Candidate { path: entry_path, name: entry.name.clone(), is_symlink, is_real_dir, meta });
+        }
+        return Ok(candidates);
+    }
+
+    for entry in fs::read_dir(path)? {
+        // The directory itself can't be read any further, e.g. `/proc/self/map_files`
+        let entry = entry?;
+        match entry.file_type() {
+            Ok(file_type) => candidates.push(Candidate {
+                path: entry.path(),
+                name: entry.file_name(),
+                is_symlink: file_type.is_symlink(),
+                is_real_dir: file_type.is_dir(),
+                meta: None
+            }),
+            Err(error) => errors.push((entry.path(), error))
+        }
+    }
+    Ok(candidates)
+}
+
+fn read_listing(job: ReadJob, worker: &Worker<ReadJob>, config: &Config) {
+    let mut errors: Vec<(PathBuf, io::Error)> = Vec::new();
+    let candidates = match read_candidates(&job.path, config, &mut errors) {
+        Ok(candidates) => candidates,
+        Err(error) => {
+            job.tx_listing.send(Err(error)).unwrap();
+            return;
+        }
+    };
+
+    // The files listed by git are shown whether they're ignored or not
+    let ignores = match config.args.no_ignore || config.tree.is_some() {
+        false => Some(IgnoreRules::for_dir(&job.path, job.ignores.clone())),
+        true => None
+    };
+
+    let mut entries: Vec<RawEntry> = Vec::with_capacity(candidates.len());
+    let mut hidden_count = 0;
+    let mut filtered_count = 0;
+    let mut ignored_count = 0;
+    // Everything under a directory counts towards its size, whether it's shown or not
+    let tree_size = |path: &Path| get_tree_size(path, config.args.du_mode);
+    let mut size = config.args.du.then(|| fs::metadata(&job.path).map_or(0, |m| get_size(&m, config.args.du_mode)));
+    for Candidate { path, name, is_symlink, is_real_dir, meta } in candidates {
+        let (is_dir, link) = if is_symlink {
+            let target = match fs::read_link(&path) {
+                Ok(target) => target,
+                Err(error) => {
+                    errors.push((path, error));
+                    continue;
+                }
+            };
+            match fs::metadata(&path) {
+                Ok(m) => (m.is_dir(), Some(LinkInfo { target, is_broken: false })),
+                Err(_) => (false, Some(LinkInfo { target, is_broken: true }))
+            }
         } else {
-            files.push(path);
+            (is_real_dir, None)
+        };
+
+        // Directories are only accounted for once it's known whether they're traversed
+        if let (Some(size), false) = (size.as_mut(), is_real_dir) {
+            *size += tree_size(&path);
+        }
+
+        if ignores.as_ref().is_some_and(|i| i.is_ignored(&path, is_dir)) {
+            if let (Some(size), true) = (size.as_mut(), is_real_dir) {
+                *size += tree_size(&path);
+            }
+            ignored_count += 1;
+            continue;
+        }
+
+        if !config.args.all && name.to_string_lossy().starts_with('.') {
+            if let (Some(size), true) = (size.as_mut(), is_real_dir) {
+                *size += tree_size(&path);
+            }
+            hidden_count += 1;
+            continue;
         }
+
+        // Excluded files are left to the compute stage, which accounts for them
+        if is_dir && config.filter.is_excluded(&path) {
+            if let (Some(size), true) = (size.as_mut(), is_real_dir) {
+                *size += tree_size(&path);
+            }
+            filtered_count += 1;
+            continue;
+        }
+
+        // Searching the content of files is the costly part, done here to spread it over the threads
+        let mut matches_count = None;
+        if let (false, Some(search)) = (is_dir, &config.search) {
+            // Files left out by the filters aren't worth reading
+            matches_count = config.is_file_valid(&path, job.depth, meta.as_ref())
+                .then(|| search.count_matches(&path))
+                .flatten()
+                .filter(|c| *c > 0);
+            if matches_count.is_none() {
+                filtered_count += 1;
+                continue;
+            }
+        }
+
+        let meta = match config.has_columns() {
+            true => meta.or_else(|| fs::symlink_metadata(&path).ok().map(|m| EntryMeta::new(&m))),
+            false => meta
+        };
+        entries.push(RawEntry { path, name, is_dir, link, matches_count, meta });
     }
 
-    let total = files.len() + dirs.len();
+    sort_entries(&mut entries, config);
+
+    let depth = job.depth;
+    // Folding over the whole directory keeps its files together whichever batch they end up in
+    let folded = Arc::new(get_folded_extensions(config, &entries, depth));
+    // The hidden entries line comes last when there's any, neither it nor the files are rendered
+    // when listing directories only. Folded files are replaced by the lines leading the first batch
+    let total = if config.args.dirs_only {
+        entries.iter().filter(|e| e.is_dir).count() + errors.len()
+    } else {
+        let folded_count = entries.iter().filter(|e| !e.is_dir && is_folded(&e.path, &folded)).count();
+        entries.len() - folded_count + folded.len() + errors.len() + usize::from(hidden_count + filtered_count > 0)
+    };
     let mut c = 0;
 
-    let mut files_info = FilesInfo {
-        files: smallvec![],
-        depth
+    let mut listing = Listing {
+        entries: Vec::with_capacity(entries.len() + errors.len()),
+        depth,
+        hidden_count,
+        filtered_count,
+        ignored_count,
+        size
     };
 
-    for file in files {
+    let mut is_first = true;
+    let mut jobs: Vec<ReadJob> = Vec::new();
+    for RawEntry { path, is_dir, link, matches_count, meta, .. } in entries {
+        if is_dir || (!config.args.dirs_only && !is_folded(&path, &folded)) {
+            c +=1;
+        }
+
+        if !is_dir {
+            if is_first {
+                c += folded.len();
+            }
+            if let Some(ListedEntry::Files(files_info)) = listing.entries.last_mut() {
+                files_info.files.push(FileInfo { path, link, matches_count, meta });
+                files_info.is_last = c == total;
+            } else {
+                listing.entries.push(ListedEntry::Files(FilesInfo {
+                    files: smallvec![FileInfo { path, link, matches_count, meta }],
+                    depth,
+                    is_last: c == total,
+                    is_first,
+                    folded: folded.clone()
+                }));
+                is_first = false;
+            }
+            continue;
+        }
+
+        // The entries of a revision aren't checked out, neither are its submodules
+        let is_checked_out = link.is_none() && config.tree.as_ref().is_none_or(|t| t.is_checked_out);
+        // Repositories collapsed by `--no-nested-repos` aren't badged, their status isn't worth a `git` process
+        let is_collapsed_repo = is_checked_out && config.args.no_nested_repos && is_repo_root(&path);
+        let repo = (is_checked_out && !config.args.no_nested_repos).then(|| RepoInfo::read(&path)).flatten();
+        let is_ignored = config.is_dir_ignored(&path) || is_collapsed_repo;
+        // Directories on other file systems are rendered like the ones past the maximum depth
+        let is_other_device = job.device.is_some_and(|d| get_device(&path).is_some_and(|pd| pd != d));
+        let is_truncated = !is_ignored && (config.is_past_max_depth(depth + 1) || is_other_device);
+        let mount = config.mounts.get_type(&path).map(|t| t.to_string());
+        let is_traversed = !is_ignored && !is_truncated && (link.is_none() || config.args.follow);
+        let ancestry = job.ancestry.as_ref()
+            .filter(|_| is_traversed)
+            .map(|a| Ancestry::new(&path, Some(a.clone())));
+        let is_recursive = match (&ancestry, &job.ancestry) {
+            (Some(a), Some(parent)) => a.id.as_ref().is_some_and(|id| parent.contains(id)),
+            _ => false
+        };
+        let is_last = c == total;
+
+        // Links are only accounted for by themselves, like files, other file systems aren't at all
+        let dir_size = match (&mut listing.size, is_traversed && !is_recursive, &link) {
+            (Some(size), false, None) if !is_other_device => {
+                let dir_size = tree_size(&path);
+                *size += dir_size;
+                Some(dir_size)
+            },
+            _ => None
+        };
+
+        let rx_listing = if is_traversed && !is_recursive {
+            let (tx_listing, rx_listing) = crossbeam_channel::bounded(1);
+            jobs.push(ReadJob { path: path.clone(), depth: depth + 1, ancestry, ignores: ignores.clone(), device: job.device, tx_listing });
+            Some(rx_listing)
+        } else {
+            None
+        };
+
+        listing.entries.push(ListedEntry::Directory(SubDirectory {
+            info: DirectoryInfo {
+                is_last,
+                is_ignored,
+                is_truncated,
+                is_leaf: rx_listing.is_none(),
+                is_recursive,
+                link,
+                meta,
+                depth,
+                size: dir_size,
+                mount,
+                repo,
+                name: path
+            },
+            rx_listing
+        }));
+    }
+
+    for (path, error) in errors {
         c +=1;
-        files_info.files.push(FileInfo {
-           path: file.path()
-        });
+        listing.entries.push(ListedEntry::Error(ErrorInfo {
+            depth,
+            is_last: c == total,
+            is_dir: false,
+            path,
+            error
+        }));
     }
 
-    tx_io.send(IOEvent::FilesListed(files_info)).unwrap();
+    // The worker pops its own jobs last in first out, pushing them in reverse keeps it busy with the
+    // directory the listing will need next, while other threads steal the later ones
+    for job in jobs.into_iter().rev() {
+        worker.push(job);
+    }
 
-    for dir in dirs {
-        let path = dir.path();
-        let is_ignored = config.is_dir_ignored(&path);
-        c +=1;
-        let is_last = (c == total);
+    job.tx_listing.send(Ok(listing)).unwrap();
+}
 
-        tx_io.send(IOEvent::DirectoryStarted(DirectoryInfo {
-            is_last,
-            is_ignored,
-            depth,
-            name: dir.path()
-        })).unwrap();
+/// The extensions of at least `extensions_fold_count` of the visible files of a directory, with their count
+fn get_folded_extensions(config: &Config, entries: &[RawEntry], depth: usize) -> Vec<(String, i32)> {
+    if config.args.unfold || config.args.dirs_only {
+        return Vec::new();
+    }
 
-        if !is_ignored {
-            list_files(&path, config, depth+1, tx_io);
+    let files: Vec<&Path> = entries.iter()
+        .filter(|e| !e.is_dir && config.is_file_valid(&e.path, depth, e.meta.as_ref()))
+        .map(|e| e.path.as_path())
+        .collect();
+    let mut counter: Counter<String> = Counter::new();
+    if files.len() >= config.settings.extensions_fold_count {
+        for file in files {
+            if let Some(ext) = get_extension(file) {
+                counter.inc(&ext);
+            }
         }
     }
+
+    let fold_count = config.settings.extensions_fold_count as i32;
+    let mut folded: Vec<(String, i32)> = counter.iter()
+        .filter(|(_, c)| **c >= fold_count)
+        .map(|(ext, c)| (ext.clone(), *c))
+        .collect();
+    folded.sort();
+    folded
 }
 
-pub fn compute(config: &Config, rx_io: &Receiver<IOEvent>, tx_render: &Sender<RenderItem>) {
-    let mut uncommited_dirs: VecDeque<DirectoryInfo> = VecDeque::with_capacity(8);
-    // let is_filtered_compute = config.is_filtered();
+fn is_folded(path: &Path, folded: &[(String, i32)]) -> bool {
+    get_extension(path).is_some_and(|ext| folded.iter().any(|(e, _)| *e == ext))
+}
+
+/// Orders the entries of a directory following `--sort`, `--reverse` and `--group`
+fn sort_entries(entries: &mut [RawEntry], config: &Config) {
+    match config.args.sort {
+        SortMode::None => {},
+        SortMode::Name => entries.sort_by(|a, b| a.name.cmp(&b.name)),
+        SortMode::Natural => entries.sort_by(|a, b| compare_natural(
+            &a.name.to_string_lossy(),
+            &b.name.to_string_lossy())),
+        SortMode::Extension => entries.sort_by(|a, b| compare_extension(
+            Path::new(&a.name),
+            Path::new(&b.name))),
+        SortMode::Size => {
+            // Largest first, sorting by name beforehand breaks ties consistently
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+            entries.sort_by_cached_key(|e| Reverse(match &e.meta {
+                Some(meta) => meta.size,
+                None => fs::symlink_metadata(&e.path).map_or(0, |m| m.len())
+            }));
+        },
+        SortMode::Mtime => {
+            // Most recent first
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+            entries.sort_by_cached_key(|e| Reverse(match &e.meta {
+                Some(meta) => meta.mtime,
+                None => fs::symlink_metadata(&e.path).and_then(|m| m.modified()).ok()
+            }));
+        }
+    }
+
+    if config.args.reverse {
+        entries.reverse();
+    }
+
+    match config.args.group {
+        GroupMode::FilesFirst => entries.sort_by_key(|e| e.is_dir),
+        GroupMode::DirsFirst => entries.sort_by_key(|e| !e.is_dir),
+        GroupMode::Mixed => {}
+    }
+}
+
+/// A visible entry waiting to be sent, along with its visible content
+struct Node {
+    item: RenderType,
+    depth: usize,
+    /// Only known ahead when no entry can be left out
+    is_last: Option<bool>,
+    children: Vec<Node>
+}
+
+impl Node {
+    fn new(item: RenderType, depth: usize, is_last: Option<bool>) -> Self {
+        Node { item, depth, is_last, children: Vec::new() }
+    }
+}
+
+/// A directory whose content is being received
+struct Frame {
+    /// The directory line, `None` for the root
+    dir: Option<Node>,
+    /// Whether any visible entry was found in the directory
+    is_visible: bool,
+    /// Whether the directory line was sent, its content is then sent as soon as possible
+    is_sent: bool
+}
+
+/// Keeps track of the directories being received, holding back the entries until it's known
+/// whether they're the last visible child of their parent.
+struct Layout<'a> {
+    tx_render: &'a Sender<RenderItem>,
+    frames: Vec<Frame>,
+    /// Whether entries may be left out, making the lister's `is_last` unreliable
+    can_hide: bool,
+    /// Whether directories are only visible once they have some visible content
+    prune: bool,
+    /// Whether directory lines depend on their content, and can only be sent once finished
+    defer_dirs: bool,
+    /// Pending children of the sent frames, the content of the others
+    children: Vec<Vec<Node>>,
+    /// Number of directory lines sent so far
+    dirs_count: usize,
+    /// Number of links among them
+    dir_links_count: usize
+}
+
+impl<'a> Layout<'a> {
+    fn new(can_hide: bool, prune: bool, defer_dirs: bool, tx_render: &'a Sender<RenderItem>) -> Self {
+        let root = Frame { dir: None, is_visible: true, is_sent: true };
+        Layout {
+            tx_render,
+            frames: vec![root],
+            can_hide: can_hide || prune,
+            prune,
+            defer_dirs,
+            children: vec![Vec::new()],
+            dirs_count: 0,
+            dir_links_count: 0
+        }
+    }
+
+    fn hint(&self, is_last: bool) -> Option<bool> {
+        (!self.can_hide).then_some(is_last)
+    }
+
+    fn start_dir(&mut self, dir: Node) {
+        self.frames.push(Frame { dir: Some(dir), is_visible: !self.prune, is_sent: false });
+        self.children.push(Vec::new());
+        if !self.prune {
+            self.update();
+        }
+    }
+
+    fn finish_dir(&mut self) {
+        let frame = self.frames.pop().unwrap();
+        let mut children = self.children.pop().unwrap();
+
+        if frame.is_sent {
+            if let Some(candidate) = children.pop() {
+                self.send(candidate, true);
+            }
+        } else if frame.is_visible {
+            let mut dir = frame.dir.unwrap();
+            dir.children = children;
+            self.add(dir);
+        }
+    }
+
+    fn is_visible(&self) -> bool {
+        self.frames.last().unwrap().is_visible
+    }
+
+    /// The line of the current directory, as long as it wasn't sent
+    fn current_dir(&mut self) -> Option<&mut RenderType> {
+        self.frames.last_mut().unwrap().dir.as_mut().map(|d| &mut d.item)
+    }
+
+    /// Makes the current directory visible without adding any entry to it
+    fn touch(&mut self) {
+        for frame in self.frames.iter_mut() {
+            frame.is_visible = true;
+        }
+        self.update();
+    }
+
+    /// Adds a visible entry to the current directory
+    fn add(&mut self, node: Node) {
+        let is_sent = self.frames.last().unwrap().is_sent;
+        let children = self.children.last_mut().unwrap();
+        if !is_sent {
+            children.push(node);
+        } else {
+            if let Some(candidate) = children.pop() {
+                self.send(candidate, false);
+            }
+            match node.is_last {
+                Some(is_last) => self.send(node, is_last),
+                None => self.children.last_mut().unwrap().push(node)
+            }
+        }
+
+        self.touch();
+    }
+
+    /// Sends the directories that became visible, as long as their position is known
+    fn update(&mut self) {
+        if self.defer_dirs {
+            return;
+        }
+
+        for i in 1..self.frames.len() {
+            if self.frames[i].is_sent {
+                continue;
+            }
+            let is_last = self.frames[i].dir.as_ref().unwrap().is_last;
+            if !self.frames[i].is_visible || !self.frames[i - 1].is_sent || is_last.is_none() {
+                break;
+            }
+
+            // The previous sibling is followed by this directory
+            if let Some(candidate) = self.children[i - 1].pop() {
+                self.send(candidate, false);
+            }
+
+            let dir = self.frames[i].dir.take().unwrap();
+            self.send(dir, is_last.unwrap());
+            self.frames[i].is_sent = true;
+
+            // Received entries are all followed by the visible directory being received, if any
+            let children = std::mem::take(&mut self.children[i]);
+            let has_next = i + 1 < self.frames.len() && self.frames[i + 1].is_visible;
+            let count = children.len();
+            for (c, child) in children.into_iter().enumerate() {
+                match (c + 1 == count, has_next, child.is_last) {
+                    (false, _, _) | (true, true, _) => self.send(child, false),
+                    (true, false, Some(is_last)) => self.send(child, is_last),
+                    (true, false, None) => self.children[i].push(child)
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, node: Node, is_last: bool) {
+        if let RenderType::Dir(d) = &node.item {
+            self.dirs_count += 1;
+            self.dir_links_count += usize::from(d.link.is_some());
+        }
+        self.tx_render.send(RenderItem {
+            item: node.item,
+            depth: node.depth,
+            is_last
+        }).unwrap();
+
+        let count = node.children.len();
+        for (c, child) in node.children.into_iter().enumerate() {
+            self.send(child, c + 1 == count);
+        }
+    }
+}
+
+/// The totals of a tree, counted as its entries go through `compute`
+#[derive(Default)]
+pub struct Summary {
+    /// Directories shown in the tree
+    pub dirs_count: usize,
+    /// Files matching the filters, whether they're shown, folded or only counted
+    pub files_count: usize,
+    /// Links among the directories and files
+    pub links_count: usize,
+    pub hidden_count: usize,
+    pub ignored_count: usize,
+    pub filtered_count: usize,
+    pub errors_count: usize,
+    /// The files directly under the root, only counted with `--file-counts`
+    pub root_files_count: Option<usize>,
+    /// The size of the whole tree with `--du`, of the files otherwise, when sizes are shown
+    pub size: Option<u64>
+}
+
+/// Filters the listed entries and sends the visible ones to the renderer.
+///
+/// Returns the totals of the tree, including the number of entries that couldn't be read.
+pub fn compute(config: &Config, rx_io: &Receiver<IOEvent>, tx_render: &Sender<RenderItem>) -> Summary {
+    // Directory lines are only complete once their content has been received
+    let defer_dirs = config.args.file_counts || config.args.du;
+    let mut layout = Layout::new(config.is_filtered(), config.is_pruned(), defer_dirs, tx_render);
+    let mut filtered_counter: Counter<usize> = Counter::new();
+    let mut files_counter: Counter<usize> = Counter::new();
+    let mut summary = Summary {
+        size: (config.has_size_column() || config.args.du).then_some(0),
+        ..Summary::default()
+    };
 
     for event in rx_io.iter() {
         match event {
             IOEvent::DirectoryStarted(ds) => {
-                uncommited_dirs.retain(|d| d.depth < ds.depth);
-                if !ds.is_ignored {
-                    uncommited_dirs.push_back(ds);
+                let is_leaf = ds.is_leaf;
+                let is_selected = config.is_dir_selected(&ds.name, ds.depth, ds.meta.as_ref());
+                let (depth, hint) = (ds.depth, layout.hint(ds.is_last));
+                let node = Node::new(get_dir_item(config, ds), depth, hint);
+                if !is_leaf {
+                    layout.start_dir(node);
+                    if is_selected {
+                        layout.touch();
+                    }
+                } else if !config.is_filtered() || !config.is_pruned() || is_selected {
+                    // Leaf directories hide their content, there's nothing to match a filter against
+                    layout.add(node);
                 }
             },
             IOEvent::FilesListed(fs) => {
-                if !fs.files.iter().any(|f| config.is_file_valid(f.path.as_path())) {
+                let depth = fs.depth;
+                let files: Vec<FileInfo> = fs.files
+                    .into_iter()
+                    .filter(|f| {
+                        let is_valid = config.is_file_valid(f.path.as_path(), depth, f.meta.as_ref());
+                        if !is_valid {
+                            filtered_counter.inc(&depth);
+                        }
+                        is_valid
+                    })
+                    .collect();
+
+                summary.files_count += files.len();
+                summary.links_count += files.iter().filter(|f| f.link.is_some()).count();
+                if let (Some(size), false) = (summary.size.as_mut(), config.args.du) {
+                    *size += files.iter().filter_map(|f| f.meta.as_ref()).map(|m| m.size).sum::<u64>();
+                }
+
+                if config.args.dirs_only {
+                    for _ in &files {
+                        files_counter.inc(&depth);
+                    }
+                    if !files.is_empty() {
+                        layout.touch();
+                    }
                     continue;
                 }
 
-                while let Some(d) = uncommited_dirs.pop_front() {
-                    tx_render.send(RenderItem {
-                        item: RenderType::Dir(FileRenderItem { path: d.name }),
-                        depth: d.depth,
-                        is_leaf: (d.is_ignored && d.is_last),
-                        is_last: d.is_last
-                    }).unwrap();
+                let nodes = fold_files(config, files, depth, &fs.folded, fs.is_first);
+                let count = nodes.len();
+                for (c, mut node) in nodes.into_iter().enumerate() {
+                    node.is_last = layout.hint(fs.is_last && c + 1 == count);
+                    layout.add(node);
+                }
+            },
+            IOEvent::DirectoryFinished(df) => {
+                let filtered_count = filtered_counter.remove(&df.depth).unwrap_or(0) as usize + df.filtered_count;
+                let hidden_count = df.hidden_count + filtered_count;
+                summary.hidden_count += df.hidden_count;
+                summary.filtered_count += filtered_count;
+                summary.ignored_count += df.ignored_count;
+                if let (0, Some(size)) = (df.depth, df.size) {
+                    summary.size = Some(size);
                 }
 
-                let mut c = 0;
-                let files_count = fs.files.len();
-                for file in fs.files {
-                    c += 1;
-                    if !config.is_file_valid(file.path.as_path()) {
-                        continue;
-                    }
+                let files_count = files_counter.remove(&df.depth).unwrap_or(0) as usize;
+                if df.depth == 0 {
+                    summary.root_files_count = config.args.file_counts.then_some(files_count);
+                }
+                if let Some(RenderType::Dir(d)) = layout.current_dir() {
+                    d.files_count = config.args.file_counts.then_some(files_count);
+                    d.set_size(df.size);
+                }
 
-                    let is_last = (c == files_count);
-                    tx_render.send(RenderItem {
-                        item: RenderType::File(FileRenderItem {path: file.path}),
-                        depth: fs.depth,
-                        is_leaf: true,
-                        is_last: false
-                    }).unwrap();
+                // Entries of a directory that isn't rendered aren't worth mentioning
+                if hidden_count > 0 && layout.is_visible() && !config.args.dirs_only {
+                    layout.add(Node::new(RenderType::Hidden(hidden_count), df.depth, Some(true)));
                 }
+
+                if df.depth > 0 {
+                    layout.finish_dir();
+                }
+            },
+            IOEvent::ReadFailed(e) => {
+                summary.errors_count += 1;
+                let item = RenderType::Error(ErrorRenderItem { path: e.path, is_dir: e.is_dir, error: e.error });
+                layout.add(Node::new(item, e.depth, layout.hint(e.is_last)));
             }
         }
+    }
 
+    layout.finish_dir();
+    summary.dirs_count = layout.dirs_count;
+    summary.links_count += layout.dir_links_count;
+    summary
+}
 
+/// Turns the files of a batch into nodes, the `folded` extensions of the directory being collapsed
+/// into a single one led by its first batch
+fn fold_files(config: &Config, files: Vec<FileInfo>, depth: usize, folded: &[(String, i32)], is_first: bool) -> Vec<Node> {
+    let mut nodes: Vec<Node> = Vec::with_capacity(files.len());
+    if is_first {
+        for (ext, count) in folded {
+            let item = RenderType::SkppedFiles(SkippedRenderIten { ext: ext.clone(), count: *count });
+            nodes.push(Node::new(item, depth, None));
+        }
     }
+
+    for file in files {
+        if is_folded(&file.path, folded) {
+            continue;
+        }
+
+        let matches_count = file.matches_count.filter(|_| config.args.count_matches);
+        let git = config.get_git_file_status(&file.path);
+        let change = config.get_git_change(&file.path);
+        let item = RenderType::File(FileRenderItem {path: file.path, link: file.link, matches_count, meta: file.meta, git, change});
+        nodes.push(Node::new(item, depth, None));
+    }
+    nodes
 }
 
-pub fn render_files(config: &Config, rx_render: Receiver<RenderItem>) {
-    let mut renderer = Renderer::new(config);
+fn get_extension(path: &Path) -> Option<String> {
+    path.extension().map(|ext| ext.to_string_lossy().to_string())
+}
+
+fn get_dir_item(config: &Config, ds: DirectoryInfo) -> RenderType {
+    let git = config.get_git_dir_status(&ds.name);
+    let change = config.get_git_change(&ds.name);
+    let mut item = DirRenderItem {
+        path: ds.name,
+        is_ignored: ds.is_ignored,
+        is_truncated: ds.is_truncated,
+        is_recursive: ds.is_recursive,
+        link: ds.link,
+        files_count: None,
+        meta: ds.meta,
+        size: None,
+        mount: ds.mount,
+        repo: ds.repo,
+        git,
+        change
+    };
+    item.set_size(ds.size);
+    RenderType::Dir(item)
+}
+
+/// Renders the tree as it's received, then the summary given by `get_summary` once it's complete.
+/// The `highlighted` entries stand out, e.g. the ones that just changed with `--watch`
+pub fn render_files(path: &Path, config: &Config, rx_render: Receiver<RenderItem>, highlighted: HashSet<PathBuf>,
+                    get_summary: impl FnOnce() -> Summary) -> Summary {
+    let mut renderer = Renderer::new(config, highlighted);
+    // With `--du` or `--file-counts` the root line shows values only known once the tree has been computed
+    let mut get_summary = Some(get_summary);
+    let summary = (config.args.du || config.args.file_counts).then(|| get_summary.take().unwrap()());
+    renderer.render_root(path, summary.as_ref());
+    // Whether the latest entry of each depth is the last of its directory
+    let mut ancestors: Vec<bool> = Vec::with_capacity(16);
     for item in rx_render.iter() {
-        for _ in 0..item.depth {
-            renderer.render_pipe_v();
+        let (meta, commit) = match &item.item {
+            RenderType::File(f) => (f.meta.as_ref(), config.get_last_file_commit(&f.path)),
+            RenderType::Dir(d) => (d.meta.as_ref(), config.get_last_dir_commit(&d.path)),
+            _ => (None, None)
+        };
+        renderer.render_columns(meta, commit);
+
+        ancestors.truncate(item.depth);
+        for is_last in &ancestors {
+            if *is_last {
+                renderer.render_indent();
+            } else {
+                renderer.render_pipe_v();
+            }
         }
+        ancestors.push(item.is_last);
 
-        if item.is_last && item.is_leaf {
+        if item.is_last {
             renderer.render_pipe_e();
         } else {
             renderer.render_pipe_t();
@@ -143,11 +1096,219 @@ pub fn render_files(config: &Config, rx_render: Receiver<RenderItem>) {
 
         renderer.render_pipe_h();
         match item.item {
-            RenderType::File(f) => renderer.render_file(&f.path),
-            RenderType::Dir(d) => renderer.render_dir(&d.path, item.is_leaf),
+            RenderType::File(f) => renderer.render_file(&f),
+            RenderType::Dir(d) => renderer.render_dir(&d),
             RenderType::SkppedFiles(s) => renderer.render_skippedfiles(&s.ext, s.count),
+            RenderType::Error(e) => renderer.render_error(&e),
+            RenderType::Hidden(count) => renderer.render_hidden(count),
         };
     }
+
+    let summary = summary.unwrap_or_else(|| get_summary.take().unwrap()());
+    if !config.args.no_report {
+        renderer.render_summary(&summary);
+    }
+    summary
 }
 
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use crate::settings::Args;
+    use super::*;
 
+    fn load_config(args: &[&str]) -> Config {
+        let args = Args::parse_from(["rusty-tree"].iter().chain(args));
+        Config::new(args, Path::new(env!("CARGO_MANIFEST_DIR")))
+    }
+
+    fn compute_tree(path: &Path, config: &Config) -> (Vec<RenderItem>, Summary) {
+        let (tx_io, rx_io) = crossbeam_channel::unbounded();
+        let (tx_render, rx_render) = crossbeam_channel::unbounded();
+        list_files(path, config, &tx_io, &|_| {});
+        drop(tx_io);
+        let summary = compute(config, &rx_io, &tx_render);
+        drop(tx_render);
+        (rx_render.iter().collect(), summary)
+    }
+
+    /// The lines of a tree, e.g. `1 └ main.rs`, along with the values shown next to the directories
+    fn describe(items: &[RenderItem]) -> Vec<String> {
+        let name = |path: &Path| path.file_name().unwrap().to_string_lossy().to_string();
+        items.iter()
+            .map(|r| {
+                let line = match &r.item {
+                    RenderType::File(f) => name(&f.path),
+                    RenderType::Dir(d) => format!("{} {:?} {:?}", name(&d.path), d.files_count, d.size),
+                    RenderType::SkppedFiles(s) => format!("{} {}", s.count, s.ext),
+                    RenderType::Error(e) => format!("{} [{}]", name(&e.path), e.error.kind()),
+                    RenderType::Hidden(count) => format!("{} hidden", count)
+                };
+                format!("{} {} {}", r.depth, if r.is_last { "└" } else { "├" }, line)
+            })
+            .collect()
+    }
+
+    fn render(layout: Layout, rx_render: &Receiver<RenderItem>) -> Vec<(usize, usize, bool)> {
+        drop(layout);
+        rx_render.try_iter()
+            .map(|r| match r.item {
+                RenderType::Hidden(id) => (id, r.depth, r.is_last),
+                _ => unreachable!()
+            })
+            .collect()
+    }
+
+    fn node(id: usize, depth: usize) -> Node {
+        Node::new(RenderType::Hidden(id), depth, None)
+    }
+
+    #[test]
+    fn test_layout_skips_empty_directories() {
+        let (tx_render, rx_render) = crossbeam_channel::unbounded();
+        let mut layout = Layout::new(true, true, false, &tx_render);
+
+        layout.start_dir(node(1, 0));
+        layout.add(node(2, 1));
+        layout.start_dir(node(3, 1));
+        layout.finish_dir();
+        layout.finish_dir();
+        layout.start_dir(node(4, 0));
+        layout.finish_dir();
+        layout.finish_dir();
+
+        assert_eq!(render(layout, &rx_render), vec![(1, 0, true), (2, 1, true)]);
+    }
+
+    #[test]
+    fn test_layout_streams_known_positions() {
+        let (tx_render, rx_render) = crossbeam_channel::unbounded();
+        let mut layout = Layout::new(false, false, false, &tx_render);
+
+        layout.start_dir(Node::new(RenderType::Hidden(1), 0, Some(false)));
+        layout.add(Node::new(RenderType::Hidden(2), 1, Some(true)));
+        assert_eq!(rx_render.len(), 2);
+
+        layout.finish_dir();
+        layout.add(Node::new(RenderType::Hidden(3), 0, Some(true)));
+        layout.finish_dir();
+
+        assert_eq!(render(layout, &rx_render), vec![(1, 0, false), (2, 1, true), (3, 0, true)]);
+    }
+
+    #[test]
+    fn test_fold_files_across_batches() {
+        let dir = std::env::temp_dir().join(format!("rusty-tree-fold-{}", std::process::id()));
+        fs::create_dir_all(dir.join("b")).unwrap();
+        fs::write(dir.join("b/inner.rs"), "").unwrap();
+        for name in ["a", "c"] {
+            for i in 0..6 {
+                fs::write(dir.join(format!("{}{}.png", name, i)), "").unwrap();
+            }
+        }
+        let mut config = load_config(&[dir.to_str().unwrap(), "--group", "mixed", "--sort", "name"]);
+        config.settings.extensions_fold_count = 5;
+
+        // The last batch is folded entirely, leaving the directory last
+        let (items, _) = compute_tree(&dir, &config);
+        assert_eq!(describe(&items), vec!["0 ├ 12 png", "0 └ b None None", "1 └ inner.rs"]);
+
+        fs::write(dir.join("d.rs"), "").unwrap();
+        let (items, _) = compute_tree(&dir, &config);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(describe(&items), vec!["0 ├ 12 png", "0 ├ b None None", "1 └ inner.rs", "0 └ d.rs"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_count_visible_links() {
+        let dir = std::env::temp_dir().join(format!("rusty-tree-links-{}", std::process::id()));
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("docs/guide.md"), "").unwrap();
+        fs::write(dir.join("src/main.rs"), "").unwrap();
+        std::os::unix::fs::symlink(dir.join("docs"), dir.join("manual")).unwrap();
+        std::os::unix::fs::symlink(dir.join("docs/guide.md"), dir.join("guide.md")).unwrap();
+        std::os::unix::fs::symlink(dir.join("src/main.rs"), dir.join("main.rs")).unwrap();
+
+        let (_, summary) = compute_tree(&dir, &load_config(&[dir.to_str().unwrap()]));
+        assert_eq!((summary.dirs_count, summary.files_count, summary.links_count), (3, 4, 3));
+
+        let (_, summary) = compute_tree(&dir, &load_config(&[dir.to_str().unwrap(), "-f", "*.rs"]));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!((summary.dirs_count, summary.files_count, summary.links_count), (1, 2, 1));
+    }
+
+    #[test]
+    fn test_count_files_of_the_root() {
+        let dir = std::env::temp_dir().join(format!("rusty-tree-root-files-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        for name in ["Cargo.toml", "README.md", "src/main.rs"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let (items, summary) = compute_tree(&dir, &load_config(&[dir.to_str().unwrap(), "-d", "--file-counts"]));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(describe(&items), vec!["0 └ src Some(1) None"]);
+        assert_eq!(summary.root_files_count, Some(2));
+    }
+
+    #[test]
+    fn test_sort_revision_by_size() {
+        let dir = std::env::temp_dir().join(format!("rusty-tree-rev-size-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, size) in [("a.txt", 10), ("b.txt", 30), ("c.txt", 20)] {
+            fs::write(dir.join(name), "x".repeat(size)).unwrap();
+        }
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "{:?}", args);
+        };
+        git(&["init", "-q"]);
+        git(&["add", "-A"]);
+        git(&["commit", "-q", "-m", "files"]);
+        // The working tree no longer matches the revision
+        fs::write(dir.join("a.txt"), "x".repeat(100)).unwrap();
+        fs::remove_file(dir.join("b.txt")).unwrap();
+
+        let root = dir.to_str().unwrap();
+        let (items, _) = compute_tree(&dir, &load_config(&[root, "--rev", "HEAD", "--sort", "size"]));
+        assert_eq!(describe(&items), vec!["0 ├ b.txt", "0 ├ c.txt", "0 └ a.txt"]);
+
+        let (items, _) = compute_tree(&dir, &load_config(&[root, "--rev", "HEAD", "--where", "size > 15"]));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(describe(&items), vec!["0 ├ b.txt", "0 ├ c.txt", "0 └ 1 hidden"]);
+    }
+
+    #[test]
+    fn test_threads_count_doesnt_change_the_tree() {
+        let dir = std::env::temp_dir().join(format!("rusty-tree-threads-{}", std::process::id()));
+        for (i, sub) in ["src/crawler", "src/render/widgets", "docs", "assets/icons", ".config"].iter().enumerate() {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+            for j in 0..i + 3 {
+                fs::write(dir.join(sub).join(format!("file{}.{}", j, ["rs", "md", "png"][j % 3])), "x".repeat(j)).unwrap();
+            }
+        }
+        fs::create_dir_all(dir.join("empty")).unwrap();
+
+        let root = dir.to_str().unwrap();
+        for args in [vec![], vec!["--du"], vec!["-f", "*.rs"], vec!["--prune", "-a"], vec!["-l"], vec!["-d", "--file-counts"]] {
+            let trees: Vec<Vec<String>> = ["1", "8"].iter()
+                .map(|threads| {
+                    let mut args = args.clone();
+                    args.extend([root, "--threads", threads]);
+                    describe(&compute_tree(&dir, &load_config(&args)).0)
+                })
+                .collect();
+            assert_eq!(trees[0], trees[1], "{:?}", args);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
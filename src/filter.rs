@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+use glob_match::glob_match;
+use regex::{Regex, RegexBuilder};
+
+enum Matcher {
+    Glob(String),
+    Regex(Regex)
+}
+
+/// A single `--include` or `--exclude` pattern
+struct Pattern {
+    matcher: Matcher,
+    /// Globs without any separator only match the file name, the others the path relative to the root
+    is_name_only: bool
+}
+
+impl Pattern {
+    fn new(pattern: &str, is_regex: bool, ignore_case: bool) -> Result<Self, regex::Error> {
+        if is_regex {
+            let regex = RegexBuilder::new(pattern).case_insensitive(ignore_case).build()?;
+            return Ok(Pattern { matcher: Matcher::Regex(regex), is_name_only: false });
+        }
+
+        let glob = if ignore_case { pattern.to_lowercase() } else { pattern.to_string() };
+        let is_name_only = !glob.contains('/');
+        Ok(Pattern { matcher: Matcher::Glob(glob.trim_start_matches('/').to_string()), is_name_only })
+    }
+
+    fn is_match(&self, relative_path: &str, ignore_case: bool) -> bool {
+        match &self.matcher {
+            Matcher::Regex(regex) => regex.is_match(relative_path),
+            Matcher::Glob(glob) => {
+                let value = if self.is_name_only {
+                    relative_path.rsplit('/').next().unwrap_or(relative_path)
+                } else {
+                    relative_path
+                };
+                if ignore_case {
+                    glob_match(glob, &value.to_lowercase())
+                } else {
+                    glob_match(glob, value)
+                }
+            }
+        }
+    }
+}
+
+/// The `--include` and `--exclude` patterns, compiled once
+pub struct Filter {
+    root: PathBuf,
+    includes: Vec<Pattern>,
+    excludes: Vec<Pattern>,
+    ignore_case: bool
+}
+
+impl Filter {
+    pub fn new(root: &Path, includes: &[String], excludes: &[String], is_regex: bool, ignore_case: bool) -> Result<Self, regex::Error> {
+        let compile = |patterns: &[String]| patterns
+            .iter()
+            .map(|p| Pattern::new(p, is_regex, ignore_case))
+            .collect::<Result<Vec<Pattern>, regex::Error>>();
+
+        Ok(Filter {
+            root: root.to_path_buf(),
+            includes: compile(includes)?,
+            excludes: compile(excludes)?,
+            ignore_case
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.includes.is_empty() || !self.excludes.is_empty()
+    }
+
+    /// Whether an entry, and all its content for a directory, is left out
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        let relative_path = self.get_relative_path(path);
+        self.excludes.iter().any(|p| p.is_match(&relative_path, self.ignore_case))
+    }
+
+    /// Whether a file is matched by an include pattern, directly or through one of its parents
+    pub fn is_included(&self, path: &Path) -> bool {
+        if self.includes.is_empty() {
+            return true;
+        }
+
+        let relative_path = self.get_relative_path(path);
+        let mut candidate = relative_path.as_str();
+        loop {
+            if self.includes.iter().any(|p| p.is_match(candidate, self.ignore_case)) {
+                return true;
+            }
+            match candidate.rfind('/') {
+                Some(pos) => candidate = &candidate[..pos],
+                None => return false
+            }
+        }
+    }
+
+    /// The path relative to the root, with `/` separators whatever the platform
+    pub fn get_relative_path(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(includes: &[&str], excludes: &[&str], is_regex: bool, ignore_case: bool) -> Filter {
+        let includes: Vec<String> = includes.iter().map(|p| p.to_string()).collect();
+        let excludes: Vec<String> = excludes.iter().map(|p| p.to_string()).collect();
+        Filter::new(Path::new("/root"), &includes, &excludes, is_regex, ignore_case).unwrap()
+    }
+
+    #[test]
+    fn test_globs() {
+        let f = filter(&["*.rs", "src/**/test_*.py", "docs"], &["target", "**/gen/*.rs"], false, false);
+
+        assert!(f.is_included(Path::new("/root/main.rs")));
+        assert!(f.is_included(Path::new("/root/src/a/b/test_io.py")));
+        assert!(!f.is_included(Path::new("/root/lib/test_io.py")));
+        assert!(f.is_included(Path::new("/root/docs/index.md")));
+        assert!(!f.is_included(Path::new("/root/src/docs.md")));
+        assert!(!f.is_included(Path::new("/root/MAIN.RS")));
+
+        assert!(f.is_excluded(Path::new("/root/target")));
+        assert!(f.is_excluded(Path::new("/root/src/gen/parser.rs")));
+        assert!(!f.is_excluded(Path::new("/root/src/parser.rs")));
+    }
+
+    #[test]
+    fn test_regex_ignore_case() {
+        let f = filter(&[r"^src/.*\.rs$"], &[], true, true);
+
+        assert!(f.is_included(Path::new("/root/SRC/Main.RS")));
+        assert!(!f.is_included(Path::new("/root/tests/main.rs")));
+        assert!(Filter::new(Path::new("/root"), &["(".to_string()], &[], true, false).is_err());
+    }
+}
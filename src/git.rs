@@ -0,0 +1,634 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::{fs, io};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The state of an entry in the working tree, by decreasing priority when rolling up directories
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GitStatus {
+    Conflicted,
+    Modified,
+    Renamed,
+    Staged,
+    Untracked,
+    Ignored
+}
+
+impl GitStatus {
+    /// Reads the `XY` code of `git status --porcelain`
+    fn parse(code: &str) -> Option<Self> {
+        let mut chars = code.chars();
+        let (x, y) = (chars.next()?, chars.next()?);
+        match (x, y) {
+            ('?', '?') => Some(GitStatus::Untracked),
+            ('!', '!') => Some(GitStatus::Ignored),
+            ('U', _) | (_, 'U') | ('A', 'A') | ('D', 'D') => Some(GitStatus::Conflicted),
+            (_, 'M') | (_, 'D') | (_, 'T') => Some(GitStatus::Modified),
+            ('R', _) => Some(GitStatus::Renamed),
+            ('M', _) | ('A', _) | ('D', _) | ('C', _) | ('T', _) => Some(GitStatus::Staged),
+            _ => None
+        }
+    }
+
+    /// Whether the entry differs from the last commit
+    pub fn is_changed(self) -> bool {
+        self != GitStatus::Ignored
+    }
+}
+
+/// The status of the working tree of the repository containing the root
+pub struct GitStatuses {
+    /// The files and directories listed by git
+    entries: HashMap<PathBuf, GitStatus>,
+    /// The most important status found under each directory
+    dirs: HashMap<PathBuf, GitStatus>
+}
+
+impl GitStatuses {
+    /// Runs `git status` on the repository containing `root`, `None` when there's none
+    pub fn load(root: &Path) -> io::Result<Option<Self>> {
+        let toplevel = match run_git(root, &["rev-parse", "--show-toplevel"])? {
+            Some(output) => PathBuf::from(output.trim_end_matches('\n')),
+            None => return Ok(None)
+        };
+        let status = run_git(&toplevel, &["status", "--porcelain=v1", "-z", "--ignored", "--untracked-files=normal"])?
+            .unwrap_or_default();
+        Ok(Some(GitStatuses::parse(&toplevel, &status)))
+    }
+
+    fn parse(toplevel: &Path, status: &str) -> Self {
+        let mut entries = HashMap::new();
+        let mut dirs: HashMap<PathBuf, GitStatus> = HashMap::new();
+
+        let mut records = status.split('\0');
+        while let Some(record) = records.next() {
+            if record.len() < 4 {
+                continue;
+            }
+            let (code, path) = record.split_at(3);
+            // Renames and copies are followed by their source
+            if code.starts_with(['R', 'C']) {
+                records.next();
+            }
+            let Some(status) = GitStatus::parse(code) else { continue };
+
+            // Untracked and ignored directories are listed as a whole, with a trailing slash
+            let path = toplevel.join(path.trim_end_matches('/'));
+            if status.is_changed() {
+                for dir in path.ancestors().skip(1).take_while(|d| d.starts_with(toplevel)) {
+                    let entry = dirs.entry(dir.to_path_buf()).or_insert(status);
+                    *entry = (*entry).min(status);
+                }
+            }
+            entries.insert(path, status);
+        }
+
+        GitStatuses { entries, dirs }
+    }
+
+    /// The status of a file, or of the untracked or ignored directory containing it
+    pub fn get_file_status(&self, path: &Path) -> Option<GitStatus> {
+        self.entries.get(path)
+            .copied()
+            .or_else(|| self.get_parent_status(path))
+    }
+
+    /// The status of a directory, rolled up from its content
+    pub fn get_dir_status(&self, path: &Path) -> Option<GitStatus> {
+        self.entries.get(path)
+            .or_else(|| self.dirs.get(path))
+            .copied()
+            .or_else(|| self.get_parent_status(path))
+    }
+
+    /// Entries inherit the status of an untracked or ignored parent directory
+    fn get_parent_status(&self, path: &Path) -> Option<GitStatus> {
+        path.ancestors()
+            .skip(1)
+            .find_map(|p| self.entries.get(p))
+            .copied()
+            .filter(|s| matches!(s, GitStatus::Untracked | GitStatus::Ignored))
+    }
+}
+
+/// How an entry changed between the two revisions of `--rev-diff`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GitChange {
+    Added,
+    Removed,
+    Modified,
+    Renamed
+}
+
+impl GitChange {
+    /// Reads the status of `git diff --name-status`, e.g. `M` or `R087`
+    fn parse(code: &str) -> Option<Self> {
+        match code.chars().next()? {
+            'A' | 'C' => Some(GitChange::Added),
+            'D' => Some(GitChange::Removed),
+            'M' | 'T' => Some(GitChange::Modified),
+            'R' => Some(GitChange::Renamed),
+            _ => None
+        }
+    }
+}
+
+/// The directories of a tree listed by git rather than read from the file system, with their entries
+pub struct GitTree {
+    dirs: HashMap<PathBuf, Vec<TreeEntry>>,
+    /// Whether the entries are in the working tree, rather than only in a revision
+    pub is_checked_out: bool,
+    /// Only set with `--rev-diff`, for the entries that changed and the directories containing them
+    changes: HashMap<PathBuf, GitChange>
+}
+
+pub struct TreeEntry {
+    pub name: OsString,
+    pub is_dir: bool,
+    /// The mode and size are only known for the entries of a revision
+    pub mode: Option<u32>,
+    pub size: Option<u64>
+}
+
+impl GitTree {
+    fn new(is_checked_out: bool) -> Self {
+        GitTree { dirs: HashMap::new(), is_checked_out, changes: HashMap::new() }
+    }
+
+    /// Lists the files of the index under `root`, `None` outside of a repository
+    pub fn load_index(root: &Path) -> io::Result<Option<Self>> {
+        let files = run_git(root, &["ls-files", "-z"])?;
+        Ok(files.map(|f| GitTree::parse_index(root, &f)))
+    }
+
+    /// Lists the files of a revision under `root`, `None` outside of a repository or for an unknown revision
+    pub fn load_revision(root: &Path, rev: &str) -> io::Result<Option<Self>> {
+        let files = run_git(root, &["ls-tree", "-r", "-l", "-z", rev])?;
+        Ok(files.map(|f| GitTree::parse_revision(root, &f)))
+    }
+
+    /// Lists the files of `to` along with the ones removed since `from`, marking what changed in between
+    pub fn load_diff(root: &Path, from: &str, to: &str) -> io::Result<Option<Self>> {
+        let (Some(from_tree), Some(mut tree)) = (GitTree::load_revision(root, from)?, GitTree::load_revision(root, to)?) else {
+            return Ok(None);
+        };
+        let diff = run_git(root, &["diff", "--name-status", "-z", "-M", "--relative", from, to])?;
+        Ok(diff.map(|d| {
+            tree.add_changes(root, &from_tree, &d);
+            tree
+        }))
+    }
+
+    fn parse_index(root: &Path, files: &str) -> Self {
+        let mut tree = GitTree::new(true);
+        // Conflicted files are listed once per stage
+        let mut last = None;
+        for file in files.split('\0').filter(|f| !f.is_empty()) {
+            if last != Some(file) {
+                tree.add(root, &root.join(file), false, None, None);
+            }
+            last = Some(file);
+        }
+        tree
+    }
+
+    /// Reads the records of `git ls-tree -l`, e.g. `100644 blob 983d366…    1012\tsrc/counter.rs`
+    fn parse_revision(root: &Path, files: &str) -> Self {
+        let mut tree = GitTree::new(false);
+        for record in files.split('\0') {
+            let Some((info, file)) = record.split_once('\t') else { continue };
+            let mut fields = info.split_whitespace();
+            let mode = fields.next().and_then(|m| u32::from_str_radix(m, 8).ok());
+            // Submodules are rendered as empty directories
+            let is_dir = fields.next() == Some("commit");
+            let size = fields.nth(1).and_then(|s| s.parse().ok());
+            tree.add(root, &root.join(file), is_dir, mode, size);
+        }
+        tree
+    }
+
+    /// Adds an entry along with the directories leading to it
+    fn add(&mut self, root: &Path, path: &Path, is_dir: bool, mode: Option<u32>, size: Option<u64>) {
+        let (mut is_dir, mut mode, mut size) = (is_dir, mode, size);
+        for entry in path.ancestors().take_while(|p| *p != root) {
+            let (Some(parent), Some(name)) = (entry.parent(), entry.file_name()) else { break };
+            // A directory already listed was added to its own parent along with a previous file
+            let is_known = self.dirs.contains_key(parent);
+            self.dirs.entry(parent.to_path_buf())
+                .or_default()
+                .push(TreeEntry { name: name.to_os_string(), is_dir, mode, size });
+            if is_known {
+                break;
+            }
+            is_dir = true;
+            // Trees have no permissions of their own, they get the usual ones of directories
+            mode = mode.map(|_| 0o040755);
+            size = None;
+        }
+    }
+
+    /// Reads the records of `git diff --name-status -z`, the removed entries being taken from `from`
+    fn add_changes(&mut self, root: &Path, from: &GitTree, diff: &str) {
+        let mut changes = Vec::new();
+        let mut records = diff.split('\0');
+        while let Some(code) = records.next() {
+            if code.is_empty() {
+                continue;
+            }
+            // Renames and copies are listed with their source first
+            if code.starts_with(['R', 'C']) {
+                records.next();
+            }
+            let Some(file) = records.next() else { break };
+            if let Some(change) = GitChange::parse(code) {
+                changes.push((root.join(file), change));
+            }
+        }
+
+        // Directories are compared before the removed entries are added back
+        for (path, _) in &changes {
+            for dir in path.ancestors().skip(1).take_while(|d| *d != root && d.starts_with(root)) {
+                let change = match (from.dirs.contains_key(dir), self.dirs.contains_key(dir)) {
+                    (false, _) => GitChange::Added,
+                    (true, false) => GitChange::Removed,
+                    (true, true) => GitChange::Modified
+                };
+                self.changes.insert(dir.to_path_buf(), change);
+            }
+        }
+
+        for (path, change) in changes {
+            if let (GitChange::Removed, Some(entry)) = (change, from.get_entry(&path)) {
+                self.add(root, &path, entry.is_dir, entry.mode, entry.size);
+            }
+            self.changes.insert(path, change);
+        }
+    }
+
+    /// The entries of a directory, in no particular order
+    pub fn get_entries(&self, path: &Path) -> &[TreeEntry] {
+        self.dirs.get(path).map_or(&[], |e| e.as_slice())
+    }
+
+    fn get_entry(&self, path: &Path) -> Option<&TreeEntry> {
+        let (parent, name) = (path.parent()?, path.file_name()?);
+        self.get_entries(parent).iter().find(|e| e.name == name)
+    }
+
+    /// How an entry or the content of a directory changed with `--rev-diff`
+    pub fn get_change(&self, path: &Path) -> Option<GitChange> {
+        self.changes.get(path).copied()
+    }
+}
+
+/// The last commit that touched an entry
+pub struct LastCommit {
+    pub hash: String,
+    pub time: SystemTime,
+    pub author: String
+}
+
+/// The last commits of the files under the root, and the most recent one under each directory
+pub struct GitHistory {
+    /// From the most recent, as listed by `git log`
+    commits: Vec<LastCommit>,
+    files: HashMap<PathBuf, usize>,
+    dirs: HashMap<PathBuf, usize>
+}
+
+impl GitHistory {
+    /// Reads the whole history of the files under `root` up to `rev`, or to `HEAD`, `None` outside of a
+    /// repository
+    pub fn load(root: &Path, rev: Option<&str>) -> io::Result<Option<Self>> {
+        let mut args = vec!["log", "--format=%x01%H%x00%at%x00%an", "--name-only", "-z", "--relative"];
+        args.extend(rev);
+        let log = run_git(root, &args)?;
+        Ok(log.map(|l| GitHistory::parse(root, &l)))
+    }
+
+    /// Reads commits made of a `\x01` and of NUL separated fields, the hash, time and author, then the files
+    fn parse(root: &Path, log: &str) -> Self {
+        let mut history = GitHistory { commits: Vec::new(), files: HashMap::new(), dirs: HashMap::new() };
+        for record in log.split('\x01').filter(|r| !r.is_empty()) {
+            let mut fields = record.split('\0');
+            let (Some(hash), Some(time), Some(author)) = (fields.next(), fields.next(), fields.next()) else { continue };
+            let index = history.commits.len();
+            history.commits.push(LastCommit {
+                hash: hash.to_string(),
+                time: UNIX_EPOCH + Duration::from_secs(time.parse().unwrap_or(0)),
+                author: author.to_string()
+            });
+
+            // The files are on a new line, merges don't list any
+            for file in fields.map(|f| f.trim_start_matches('\n')).filter(|f| !f.is_empty()) {
+                let path = root.join(file);
+                if history.files.contains_key(&path) {
+                    continue;
+                }
+                for dir in path.ancestors().skip(1).take_while(|d| d.starts_with(root)) {
+                    // The ancestors of a directory already known were touched more recently too
+                    if history.dirs.contains_key(dir) {
+                        break;
+                    }
+                    history.dirs.insert(dir.to_path_buf(), index);
+                }
+                history.files.insert(path, index);
+            }
+        }
+        history
+    }
+
+    pub fn get_file_commit(&self, path: &Path) -> Option<&LastCommit> {
+        self.files.get(path).map(|i| &self.commits[*i])
+    }
+
+    /// The most recent commit among the content of a directory
+    pub fn get_dir_commit(&self, path: &Path) -> Option<&LastCommit> {
+        self.dirs.get(path).map(|i| &self.commits[*i])
+    }
+}
+
+/// How often an entry changed with `--churn`
+#[derive(Clone, Copy)]
+pub struct Churn {
+    pub commits: usize,
+    /// Relatively to the most changed file, or directory
+    pub ratio: f64
+}
+
+/// The number of commits that touched each file under the root, and each directory
+pub struct GitChurn {
+    files: HashMap<PathBuf, usize>,
+    dirs: HashMap<PathBuf, usize>,
+    max_file_commits: usize,
+    max_dir_commits: usize
+}
+
+impl GitChurn {
+    /// Counts the commits since a date git understands, e.g. `3.months` or `2024-01-01`, or over the whole
+    /// history, up to `rev` or `HEAD`. `None` outside of a repository
+    pub fn load(root: &Path, since: Option<&str>, rev: Option<&str>) -> io::Result<Option<Self>> {
+        let since = since.map(|s| format!("--since={}", s));
+        let mut args = vec!["log", "--format=%x01%H", "--name-only", "-z", "--relative"];
+        args.extend(since.as_deref());
+        args.extend(rev);
+        let log = run_git(root, &args)?;
+        Ok(log.map(|l| GitChurn::parse(root, &l)))
+    }
+
+    /// Reads commits made of a `\x01` and of their hash, followed by the files, all separated by NUL
+    fn parse(root: &Path, log: &str) -> Self {
+        let mut files: HashMap<PathBuf, usize> = HashMap::new();
+        let mut dirs: HashMap<PathBuf, usize> = HashMap::new();
+        for record in log.split('\x01') {
+            // The files are on a new line, merges don't list any
+            let commit_files: Vec<PathBuf> = record.split('\0')
+                .skip(1)
+                .map(|f| f.trim_start_matches('\n'))
+                .filter(|f| !f.is_empty())
+                .map(|f| root.join(f))
+                .collect();
+
+            // Directories count the commits touching their content, rather than the changed files
+            let mut commit_dirs: HashSet<&Path> = HashSet::new();
+            for path in &commit_files {
+                *files.entry(path.clone()).or_default() += 1;
+                commit_dirs.extend(path.ancestors().skip(1).take_while(|d| d.starts_with(root)));
+            }
+            for dir in &commit_dirs {
+                *dirs.entry(dir.to_path_buf()).or_default() += 1;
+            }
+        }
+
+        // The root is left out, it would always be the most changed directory
+        let max_file_commits = files.values().copied().max().unwrap_or(0);
+        let max_dir_commits = dirs.iter().filter(|(d, _)| *d != root).map(|(_, c)| *c).max().unwrap_or(0);
+        GitChurn { files, dirs, max_file_commits, max_dir_commits }
+    }
+
+    pub fn get_file_churn(&self, path: &Path) -> Churn {
+        let commits = self.files.get(path).copied().unwrap_or(0);
+        Churn { commits, ratio: get_ratio(commits, self.max_file_commits) }
+    }
+
+    pub fn get_dir_churn(&self, path: &Path) -> Churn {
+        let commits = self.dirs.get(path).copied().unwrap_or(0);
+        Churn { commits, ratio: get_ratio(commits, self.max_dir_commits) }
+    }
+}
+
+fn get_ratio(value: usize, max: usize) -> f64 {
+    match max {
+        0 => 0.0,
+        _ => (value as f64 / max as f64).min(1.0)
+    }
+}
+
+/// A repository nested in the tree, or a submodule
+pub struct RepoInfo {
+    /// The current branch, or the abbreviated hash of a detached `HEAD`
+    pub head: String,
+    /// Whether there are uncommitted changes or untracked files
+    pub is_dirty: bool
+}
+
+impl RepoInfo {
+    /// Reads the repository of a directory containing a `.git` directory, or a `.git` file pointing to the
+    /// actual one as submodules and worktrees do
+    pub fn read(dir: &Path) -> Option<Self> {
+        let git_dir = find_git_dir(dir)?;
+        let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+        let head = match head.trim().strip_prefix("ref:") {
+            Some(reference) => reference.trim().trim_start_matches("refs/heads/").to_string(),
+            None => head.trim().chars().take(7).collect()
+        };
+        let is_dirty = run_git(dir, &["status", "--porcelain"])
+            .ok()
+            .flatten()
+            .is_some_and(|s| !s.is_empty());
+        Some(RepoInfo { head, is_dirty })
+    }
+}
+
+/// Whether a directory is the root of a repository, without reading its state
+pub fn is_repo_root(dir: &Path) -> bool {
+    find_git_dir(dir).is_some()
+}
+
+fn find_git_dir(dir: &Path) -> Option<PathBuf> {
+    let dot_git = dir.join(".git");
+    match fs::symlink_metadata(&dot_git).ok()?.is_dir() {
+        true => Some(dot_git),
+        false => {
+            let content = fs::read_to_string(&dot_git).ok()?;
+            Some(dir.join(content.strip_prefix("gitdir:")?.trim()))
+        }
+    }
+}
+
+/// Runs a git command, `None` when it fails, e.g. outside of a repository
+fn run_git(dir: &Path, args: &[&str]) -> io::Result<Option<String>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        // Reading the status mustn't take the index lock
+        .env("GIT_OPTIONAL_LOCKS", "0")
+        .output()?;
+    Ok(output.status.success().then(|| String::from_utf8_lossy(&output.stdout).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let status = [
+            " M src/main.rs",
+            "M  src/lib.rs",
+            "MM src/both.rs",
+            "R  src/new.rs", "src/old.rs",
+            "UU src/conflict.rs",
+            "?? docs/",
+            "!! target/",
+            " D gone/deleted.rs",
+            ""
+        ].join("\0");
+        let statuses = GitStatuses::parse(Path::new("/repo"), &status);
+        let file = |p: &str| statuses.get_file_status(Path::new(p));
+        let dir = |p: &str| statuses.get_dir_status(Path::new(p));
+
+        assert_eq!(file("/repo/src/main.rs"), Some(GitStatus::Modified));
+        assert_eq!(file("/repo/src/lib.rs"), Some(GitStatus::Staged));
+        assert_eq!(file("/repo/src/both.rs"), Some(GitStatus::Modified));
+        assert_eq!(file("/repo/src/new.rs"), Some(GitStatus::Renamed));
+        assert_eq!(file("/repo/src/old.rs"), None);
+        assert_eq!(file("/repo/src/conflict.rs"), Some(GitStatus::Conflicted));
+        assert_eq!(file("/repo/docs/guide/intro.md"), Some(GitStatus::Untracked));
+        assert_eq!(file("/repo/target/debug/app"), Some(GitStatus::Ignored));
+        assert_eq!(file("/repo/README.md"), None);
+
+        assert_eq!(dir("/repo/src"), Some(GitStatus::Conflicted));
+        assert_eq!(dir("/repo/gone"), Some(GitStatus::Modified));
+        assert_eq!(dir("/repo/docs"), Some(GitStatus::Untracked));
+        assert_eq!(dir("/repo/target/debug"), Some(GitStatus::Ignored));
+        assert_eq!(dir("/repo/data"), None);
+    }
+
+    #[test]
+    fn test_parse_history() {
+        let log = [
+            "\x01ccc", "1700000300", "Ada", "\nsrc/main.rs", "src/net/client.rs",
+            "\x01bbb", "1700000200", "Linus", "",
+            "\x01aaa", "1700000100", "Grace", "\nsrc/main.rs", "docs/guide.md", ""
+        ].join("\0");
+        let history = GitHistory::parse(Path::new("/repo"), &log);
+        let file = |p: &str| history.get_file_commit(Path::new(p)).map(|c| c.hash.as_str());
+        let dir = |p: &str| history.get_dir_commit(Path::new(p)).map(|c| c.hash.as_str());
+
+        assert_eq!(file("/repo/src/main.rs"), Some("ccc"));
+        assert_eq!(file("/repo/docs/guide.md"), Some("aaa"));
+        assert_eq!(file("/repo/README.md"), None);
+        assert_eq!(dir("/repo/src/net"), Some("ccc"));
+        assert_eq!(dir("/repo/docs"), Some("aaa"));
+        assert_eq!(dir("/repo"), Some("ccc"));
+
+        let commit = history.get_file_commit(Path::new("/repo/docs/guide.md")).unwrap();
+        assert_eq!(commit.author, "Grace");
+        assert_eq!(commit.time, UNIX_EPOCH + Duration::from_secs(1_700_000_100));
+    }
+
+    #[test]
+    fn test_parse_churn() {
+        let log = [
+            "\x01ccc", "\nsrc/main.rs", "src/net/client.rs", "src/net/server.rs",
+            "\x01bbb", "",
+            "\x01aaa", "\nsrc/main.rs", "docs/guide.md", ""
+        ].join("\0");
+        let churn = GitChurn::parse(Path::new("/repo"), &log);
+        let file = |p: &str| churn.get_file_churn(Path::new(p));
+        let dir = |p: &str| churn.get_dir_churn(Path::new(p));
+
+        assert_eq!(file("/repo/src/main.rs").commits, 2);
+        assert_eq!(file("/repo/src/main.rs").ratio, 1.0);
+        assert_eq!(file("/repo/docs/guide.md").ratio, 0.5);
+        assert_eq!(file("/repo/README.md").commits, 0);
+        assert_eq!(dir("/repo/src").commits, 2);
+        assert_eq!(dir("/repo/src/net").commits, 1);
+        assert_eq!(dir("/repo/src/net").ratio, 0.5);
+        assert_eq!(dir("/repo").commits, 2);
+    }
+
+    #[test]
+    fn test_read_repo() {
+        let dir = std::env::temp_dir().join(format!("rusty-tree-repos-{}", std::process::id()));
+        fs::create_dir_all(dir.join("app/.git")).unwrap();
+        fs::write(dir.join("app/.git/HEAD"), "ref: refs/heads/feature/login\n").unwrap();
+        fs::create_dir_all(dir.join("modules/lib")).unwrap();
+        fs::write(dir.join("modules/lib/HEAD"), "3f2a9c1d0b8e7f6a5b4c3d2e1f0a9b8c7d6e5f4a\n").unwrap();
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib/.git"), "gitdir: ../modules/lib\n").unwrap();
+
+        assert_eq!(RepoInfo::read(&dir.join("app")).map(|r| r.head), Some("feature/login".to_string()));
+        assert_eq!(RepoInfo::read(&dir.join("lib")).map(|r| r.head), Some("3f2a9c1".to_string()));
+        assert!(RepoInfo::read(&dir.join("modules")).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_tree() {
+        let files = ["Cargo.toml", "src/main.rs", "src/git/mod.rs", "src/lib.rs", "src/lib.rs", ""].join("\0");
+        let tree = GitTree::parse_index(Path::new("/repo"), &files);
+        let entries = |p: &str| tree.get_entries(Path::new(p))
+            .iter()
+            .map(|e| (e.name.to_string_lossy().to_string(), e.is_dir))
+            .collect::<Vec<_>>();
+
+        let entry = |name: &str, is_dir| (name.to_string(), is_dir);
+        assert_eq!(entries("/repo"), vec![entry("Cargo.toml", false), entry("src", true)]);
+        assert_eq!(entries("/repo/src"), vec![entry("main.rs", false), entry("git", true), entry("lib.rs", false)]);
+        assert_eq!(entries("/repo/src/git"), vec![entry("mod.rs", false)]);
+        assert!(entries("/repo/target").is_empty());
+    }
+
+    #[test]
+    fn test_parse_diff() {
+        let root = Path::new("/repo");
+        let from = GitTree::parse_revision(root, &[
+            "100644 blob 1111111     120\tsrc/main.rs",
+            "100644 blob 2222222      80\tsrc/old.rs",
+            "100644 blob 3333333      40\tdocs/guide.md",
+            ""
+        ].join("\0"));
+        let mut tree = GitTree::parse_revision(root, &[
+            "100644 blob 4444444     150\tsrc/main.rs",
+            "100644 blob 2222222      80\tsrc/new.rs",
+            "100755 blob 5555555      10\tscripts/build.sh",
+            "160000 commit 6666666       -\tvendor/lib",
+            ""
+        ].join("\0"));
+        let diff = ["M", "src/main.rs", "R100", "src/old.rs", "src/new.rs", "D", "docs/guide.md", "A", "scripts/build.sh", ""].join("\0");
+        tree.add_changes(root, &from, &diff);
+
+        let change = |p: &str| tree.get_change(Path::new(p));
+        assert_eq!(change("/repo/src/main.rs"), Some(GitChange::Modified));
+        assert_eq!(change("/repo/src/new.rs"), Some(GitChange::Renamed));
+        assert_eq!(change("/repo/docs/guide.md"), Some(GitChange::Removed));
+        assert_eq!(change("/repo/scripts/build.sh"), Some(GitChange::Added));
+        assert_eq!(change("/repo/src"), Some(GitChange::Modified));
+        assert_eq!(change("/repo/docs"), Some(GitChange::Removed));
+        assert_eq!(change("/repo/scripts"), Some(GitChange::Added));
+        assert_eq!(change("/repo/vendor"), None);
+
+        let removed = tree.get_entry(Path::new("/repo/docs/guide.md")).unwrap();
+        assert_eq!((removed.mode, removed.size), (Some(0o100644), Some(40)));
+        let script = tree.get_entry(Path::new("/repo/scripts/build.sh")).unwrap();
+        assert_eq!((script.mode, script.size), (Some(0o100755), Some(10)));
+        assert!(tree.get_entry(Path::new("/repo/vendor/lib")).unwrap().is_dir);
+        assert_eq!(tree.get_entries(root).len(), 4);
+    }
+}
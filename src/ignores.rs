@@ -0,0 +1,170 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use glob_match::glob_match;
+
+/// Ignore files read in every directory, by increasing precedence
+const IGNORE_FILES: [&str; 3] = [".gitignore", ".ignore", ".rustytreeignore"];
+
+/// A single line of an ignore file, following the gitignore syntax
+struct Pattern {
+    glob: String,
+    is_negated: bool,
+    is_dir_only: bool
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (line, is_negated) = match line.strip_prefix('!') {
+            Some(line) => (line, true),
+            None => (line.strip_prefix('\\').unwrap_or(line), false)
+        };
+        let (line, is_dir_only) = match line.strip_suffix('/') {
+            Some(line) => (line, true),
+            None => (line, false)
+        };
+        if line.is_empty() {
+            return None;
+        }
+
+        // A pattern without any separator matches at any depth, otherwise it's relative to the ignore file
+        let glob = if line.contains('/') {
+            line.trim_start_matches('/').to_string()
+        } else {
+            format!("**/{}", line)
+        };
+
+        Some(Pattern { glob, is_negated, is_dir_only })
+    }
+
+    fn is_match(&self, relative_path: &str, is_dir: bool) -> bool {
+        (is_dir || !self.is_dir_only) && glob_match(&self.glob, relative_path)
+    }
+}
+
+/// The patterns of one ignore file, applying to the content of `base`
+struct IgnoreFile {
+    base: PathBuf,
+    patterns: Vec<Pattern>
+}
+
+impl IgnoreFile {
+    fn load(path: &Path, base: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        Some(IgnoreFile::parse(&content, base))
+    }
+
+    fn parse(content: &str, base: &Path) -> Self {
+        IgnoreFile {
+            base: base.to_path_buf(),
+            patterns: content.lines().filter_map(Pattern::parse).collect()
+        }
+    }
+
+    /// The last matching pattern wins, `None` means this file has no opinion on `path`
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative_path = path.strip_prefix(&self.base).ok()?;
+        let relative_path = relative_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        self.patterns
+            .iter()
+            .rev()
+            .find(|p| p.is_match(&relative_path, is_dir))
+            .map(|p| !p.is_negated)
+    }
+}
+
+/// The ignore files in effect in a directory, chained to the ones of its parents
+pub struct IgnoreRules {
+    files: Vec<IgnoreFile>,
+    parent: Option<Arc<IgnoreRules>>
+}
+
+impl IgnoreRules {
+    /// Loads the rules applying above `root`, from the repository it belongs to
+    pub fn for_parents(root: &Path) -> Option<Arc<Self>> {
+        let repository = root.ancestors().find(|p| p.join(".git").exists())?;
+
+        let exclude = IgnoreFile::load(&repository.join(".git/info/exclude"), repository);
+        let mut rules = Arc::new(IgnoreRules { files: exclude.into_iter().collect(), parent: None });
+
+        let mut parents: Vec<&Path> = root.ancestors()
+            .skip(1)
+            .take_while(|p| p.starts_with(repository))
+            .collect();
+        parents.reverse();
+        for dir in parents {
+            rules = IgnoreRules::for_dir(dir, Some(rules));
+        }
+        Some(rules)
+    }
+
+    /// Loads the ignore files of `dir`, reusing the parent rules when there's none
+    pub fn for_dir(dir: &Path, parent: Option<Arc<Self>>) -> Arc<Self> {
+        let files: Vec<IgnoreFile> = IGNORE_FILES
+            .iter()
+            .filter_map(|f| IgnoreFile::load(&dir.join(f), dir))
+            .collect();
+
+        match parent {
+            Some(parent) if files.is_empty() => parent,
+            parent => Arc::new(IgnoreRules { files, parent })
+        }
+    }
+
+    /// Deeper rules take precedence over their parents, so do the files read last
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut rules = Some(self);
+        while let Some(r) = rules {
+            if let Some(is_ignored) = r.files.iter().rev().find_map(|f| f.is_ignored(path, is_dir)) {
+                return is_ignored;
+            }
+            rules = r.parent.as_deref();
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(content: &str, parent: Option<Arc<IgnoreRules>>, base: &str) -> Arc<IgnoreRules> {
+        Arc::new(IgnoreRules { files: vec![IgnoreFile::parse(content, Path::new(base))], parent })
+    }
+
+    #[test]
+    fn test_is_ignored() {
+        let r = rules("# build outputs\n*.o\n/out\nlogs/\ndocs/*.md\n!keep.o\n", None, "/repo");
+
+        assert!(r.is_ignored(Path::new("/repo/main.o"), false));
+        assert!(r.is_ignored(Path::new("/repo/src/deep/lib.o"), false));
+        assert!(!r.is_ignored(Path::new("/repo/src/keep.o"), false));
+        assert!(r.is_ignored(Path::new("/repo/out"), true));
+        assert!(!r.is_ignored(Path::new("/repo/src/out"), true));
+        assert!(r.is_ignored(Path::new("/repo/src/logs"), true));
+        assert!(!r.is_ignored(Path::new("/repo/src/logs"), false));
+        assert!(r.is_ignored(Path::new("/repo/docs/index.md"), false));
+        assert!(!r.is_ignored(Path::new("/repo/docs/api/index.md"), false));
+        assert!(!r.is_ignored(Path::new("/repo/README.md"), false));
+    }
+
+    #[test]
+    fn test_nested_rules() {
+        let root = rules("*.log\n", None, "/repo");
+        let nested = rules("!debug.log\n", Some(root), "/repo/app");
+
+        assert!(nested.is_ignored(Path::new("/repo/app/trace.log"), false));
+        assert!(!nested.is_ignored(Path::new("/repo/app/debug.log"), false));
+        assert!(nested.is_ignored(Path::new("/repo/debug.log"), false));
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::path::Path;
+use std::time::SystemTime;
+use chrono::{DateTime, Local};
+use crate::settings::{DuMode, SizeFormat, TimeFormat};
+
+/// The metadata shown in the `--long` columns, read while listing the entries
+pub struct EntryMeta {
+    pub size: u64,
+    pub mtime: Option<SystemTime>,
+    /// The unix file type and permission bits, `None` on other platforms
+    pub mode: Option<u32>,
+    pub uid: Option<u32>
+}
+
+impl EntryMeta {
+    pub fn new(metadata: &Metadata) -> Self {
+        EntryMeta {
+            size: metadata.len(),
+            mtime: metadata.modified().ok(),
+            mode: get_mode(metadata),
+            uid: get_owner(metadata)
+        }
+    }
+}
+
+/// The size of an entry as counted by `--du`
+pub fn get_size(metadata: &Metadata, mode: DuMode) -> u64 {
+    match mode {
+        DuMode::Apparent => metadata.len(),
+        DuMode::Blocks => get_allocated_size(metadata)
+    }
+}
+
+#[cfg(unix)]
+fn get_allocated_size(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn get_allocated_size(metadata: &Metadata) -> u64 {
+    metadata.len()
+}
+
+/// The size of an entry and all its content, links not being followed and unreadable entries
+/// being left out
+pub fn get_tree_size(path: &Path, mode: DuMode) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else { return 0 };
+    let mut size = get_size(&metadata, mode);
+    if metadata.is_dir() {
+        if let Ok(entries) = fs::read_dir(path) {
+            size += entries
+                .filter_map(|e| e.ok())
+                .map(|e| get_tree_size(&e.path(), mode))
+                .sum::<u64>();
+        }
+    }
+    size
+}
+
+#[cfg(unix)]
+fn get_mode(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.mode())
+}
+
+#[cfg(not(unix))]
+fn get_mode(_metadata: &Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+pub fn get_owner(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.uid())
+}
+
+#[cfg(not(unix))]
+pub fn get_owner(_metadata: &Metadata) -> Option<u32> {
+    None
+}
+
+/// Maps the user ids to their names, empty when there's no `/etc/passwd`
+pub fn load_user_names() -> HashMap<u32, String> {
+    let passwd = fs::read_to_string("/etc/passwd").unwrap_or_default();
+    passwd.lines()
+        .filter_map(|l| {
+            let mut fields = l.split(':');
+            let name = fields.next()?;
+            let uid = fields.nth(1)?.parse().ok()?;
+            Some((uid, name.to_string()))
+        })
+        .collect()
+}
+
+/// The id of a user, given by name or directly by id
+pub fn get_user_id(name: &str) -> Option<u32> {
+    load_user_names()
+        .into_iter()
+        .find(|(_, n)| n == name)
+        .map(|(uid, _)| uid)
+        .or_else(|| name.parse().ok())
+}
+
+/// Formats a size the way `ls -h` does, with a single decimal for small values only
+pub fn format_size(size: u64, format: SizeFormat) -> String {
+    let (base, units) = match format {
+        SizeFormat::Bytes => return size.to_string(),
+        SizeFormat::Iec => (1024.0, ["K", "M", "G", "T", "P", "E"]),
+        SizeFormat::Si => (1000.0, ["k", "M", "G", "T", "P", "E"])
+    };
+
+    let mut value = size as f64;
+    if value < base {
+        return size.to_string();
+    }
+    for unit in units {
+        value /= base;
+        if value < 10.0 {
+            return format!("{:.1}{}", (value * 10.0).ceil() / 10.0, unit);
+        }
+        if value.ceil() < base {
+            return format!("{}{}", value.ceil(), unit);
+        }
+    }
+    format!("{}{}", value.ceil(), units[units.len() - 1])
+}
+
+/// The width of the size column, so that it can be right aligned
+pub fn get_size_width(format: SizeFormat) -> usize {
+    match format {
+        SizeFormat::Bytes => 12,
+        SizeFormat::Iec | SizeFormat::Si => 5
+    }
+}
+
+/// Formats a time in the local time zone, or relatively to `now`
+pub fn format_time(time: SystemTime, format: TimeFormat, now: SystemTime) -> String {
+    match format {
+        TimeFormat::Absolute => DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M").to_string(),
+        TimeFormat::Iso => DateTime::<Local>::from(time).format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+        TimeFormat::Relative => {
+            let (seconds, is_future) = match now.duration_since(time) {
+                Ok(age) => (age.as_secs(), false),
+                Err(e) => (e.duration().as_secs(), true)
+            };
+            let value = match seconds {
+                0..=59 => return "just now".to_string(),
+                60..=3599 => format!("{}min", seconds / 60),
+                3600..=86_399 => format!("{}h", seconds / 3600),
+                86_400..=604_799 => format!("{}d", seconds / 86_400),
+                604_800..=2_629_799 => format!("{}w", seconds / 604_800),
+                2_629_800..=31_557_599 => format!("{}mo", seconds / 2_629_800),
+                _ => format!("{}y", seconds / 31_557_600)
+            };
+            if is_future { format!("in {}", value) } else { format!("{} ago", value) }
+        }
+    }
+}
+
+pub fn get_time_width(format: TimeFormat) -> usize {
+    match format {
+        TimeFormat::Absolute => 16,
+        TimeFormat::Iso => 25,
+        TimeFormat::Relative => 9
+    }
+}
+
+/// Formats unix permissions like `ls -l` does, e.g. `drwxr-xr-x`
+pub fn format_permissions(mode: u32) -> String {
+    let kind = match mode & 0o170000 {
+        0o040000 => 'd',
+        0o120000 => 'l',
+        0o060000 => 'b',
+        0o020000 => 'c',
+        0o010000 => 'p',
+        0o140000 => 's',
+        _ => '-'
+    };
+
+    let mut value = String::with_capacity(10);
+    value.push(kind);
+    for (shift, special, special_char) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = (mode >> shift) & 0o7;
+        value.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        value.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        value.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-'
+        });
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(1000, SizeFormat::Iec), "1000");
+        assert_eq!(format_size(1024, SizeFormat::Iec), "1.0K");
+        assert_eq!(format_size(1500, SizeFormat::Iec), "1.5K");
+        assert_eq!(format_size(1500, SizeFormat::Si), "1.5k");
+        assert_eq!(format_size(52_000, SizeFormat::Iec), "51K");
+        assert_eq!(format_size(1_048_000, SizeFormat::Iec), "1.0M");
+        assert_eq!(format_size(3 << 30, SizeFormat::Iec), "3.0G");
+        assert_eq!(format_size(52_000, SizeFormat::Bytes), "52000");
+    }
+
+    #[test]
+    fn test_get_tree_size() {
+        let dir = std::env::temp_dir().join(format!("rusty-tree-du-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), [0u8; 100]).unwrap();
+        fs::write(dir.join("sub/b.txt"), [0u8; 50]).unwrap();
+
+        let dirs_size = fs::metadata(&dir).unwrap().len() + fs::metadata(dir.join("sub")).unwrap().len();
+        assert_eq!(get_tree_size(&dir, DuMode::Apparent), dirs_size + 150);
+        assert_eq!(get_tree_size(&dir.join("a.txt"), DuMode::Apparent), 100);
+        assert_eq!(get_tree_size(&dir.join("missing"), DuMode::Apparent), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_time() {
+        let now = SystemTime::now();
+        assert_eq!(format_time(now - Duration::from_secs(30), TimeFormat::Relative, now), "just now");
+        assert_eq!(format_time(now - Duration::from_secs(3 * 3600), TimeFormat::Relative, now), "3h ago");
+        assert_eq!(format_time(now - Duration::from_secs(9 * 86_400), TimeFormat::Relative, now), "1w ago");
+        assert_eq!(format_time(now + Duration::from_secs(120), TimeFormat::Relative, now), "in 2min");
+        assert_eq!(format_time(now, TimeFormat::Absolute, now).len(), get_time_width(TimeFormat::Absolute));
+        assert_eq!(format_time(now, TimeFormat::Iso, now).len(), get_time_width(TimeFormat::Iso));
+    }
+
+    #[test]
+    fn test_format_permissions() {
+        assert_eq!(format_permissions(0o040755), "drwxr-xr-x");
+        assert_eq!(format_permissions(0o100644), "-rw-r--r--");
+        assert_eq!(format_permissions(0o104755), "-rwsr-xr-x");
+        assert_eq!(format_permissions(0o041777), "drwxrwxrwt");
+        assert_eq!(format_permissions(0o120777), "lrwxrwxrwx");
+    }
+}
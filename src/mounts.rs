@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The mount points of the system and their file system type, read from `/proc/self/mountinfo`
+pub struct Mounts {
+    types: HashMap<PathBuf, String>
+}
+
+impl Mounts {
+    /// Empty where there's no `/proc/self/mountinfo`, mount points still being detected by device
+    pub fn load() -> Self {
+        let content = fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+        Mounts::parse(&content)
+    }
+
+    fn parse(content: &str) -> Self {
+        let types = content.lines().filter_map(parse_line).collect();
+        Mounts { types }
+    }
+
+    /// The file system type of a mount point, `None` for any other directory
+    pub fn get_type(&self, path: &Path) -> Option<&str> {
+        self.types.get(path).map(|t| t.as_str())
+    }
+}
+
+/// Reads the mount point and file system type of a line, e.g.
+/// `36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue`
+fn parse_line(line: &str) -> Option<(PathBuf, String)> {
+    let mut fields = line.split(' ');
+    let mount_point = fields.nth(4)?;
+    // Optional fields come next, up to the separator
+    let fs_type = fields.skip_while(|f| *f != "-").nth(1)?;
+    Some((PathBuf::from(unescape(mount_point)), fs_type.to_string()))
+}
+
+/// Spaces, tabs, new lines and backslashes are escaped as octal sequences, e.g. `\040`
+fn unescape(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let raw = value.as_bytes();
+    let mut i = 0;
+    while i < raw.len() {
+        let code = raw.get(i + 1..i + 4)
+            .filter(|_| raw[i] == b'\\')
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u8::from_str_radix(d, 8).ok());
+        match code {
+            Some(c) => {
+                bytes.push(c);
+                i += 4;
+            },
+            None => {
+                bytes.push(raw[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mounts = Mounts::parse("\
+            22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n\
+            36 22 0:32 / /mnt/my\\040share rw,nosuid shared:20 master:3 - fuse.sshfs host:/ rw\n\
+            41 22 0:35 / /proc rw - proc proc rw\n\
+            broken line\n");
+
+        assert_eq!(mounts.get_type(Path::new("/")), Some("ext4"));
+        assert_eq!(mounts.get_type(Path::new("/mnt/my share")), Some("fuse.sshfs"));
+        assert_eq!(mounts.get_type(Path::new("/proc")), Some("proc"));
+        assert_eq!(mounts.get_type(Path::new("/mnt")), None);
+    }
+}
@@ -0,0 +1,542 @@
+use std::cell::OnceCell;
+use std::fmt;
+use std::fs::{self, Metadata};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use regex::Regex;
+use crate::metadata::{EntryMeta, get_owner, get_user_id};
+
+/// An error found while parsing or type checking a `--where` expression, `position` being the
+/// character offset it refers to
+#[derive(Debug)]
+pub struct QueryError {
+    pub message: String,
+    pub position: usize
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at character {})", self.message, self.position + 1)
+    }
+}
+
+fn error<T>(message: impl Into<String>, position: usize) -> Result<T, QueryError> {
+    Err(QueryError { message: message.into(), position })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    /// A number and its unit, if any, resolved against the field it's compared to
+    Number(u64, String),
+    Op(Operator),
+    And,
+    Or,
+    Not,
+    Open,
+    Close
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Match
+}
+
+impl Operator {
+    fn compare<T: PartialOrd>(self, a: T, b: T) -> bool {
+        match self {
+            Operator::Eq => a == b,
+            Operator::Ne => a != b,
+            Operator::Lt => a < b,
+            Operator::Le => a <= b,
+            Operator::Gt => a > b,
+            Operator::Ge => a >= b,
+            Operator::Match => false
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, QueryError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let next = chars.get(i + 1).copied();
+
+        let token = match c {
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            },
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '~' => Token::Op(Operator::Match),
+            '&' if next == Some('&') => { i += 1; Token::And },
+            '|' if next == Some('|') => { i += 1; Token::Or },
+            '=' if next == Some('=') => { i += 1; Token::Op(Operator::Eq) },
+            '!' if next == Some('=') => { i += 1; Token::Op(Operator::Ne) },
+            '!' => Token::Not,
+            '<' if next == Some('=') => { i += 1; Token::Op(Operator::Le) },
+            '<' => Token::Op(Operator::Lt),
+            '>' if next == Some('=') => { i += 1; Token::Op(Operator::Ge) },
+            '>' => Token::Op(Operator::Gt),
+            '"' | '\'' => {
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return error("unterminated string", start),
+                        Some('\\') if i + 1 < chars.len() => {
+                            value.push(chars[i + 1]);
+                            i += 2;
+                        },
+                        Some(&q) if q == c => break,
+                        Some(&v) => {
+                            value.push(v);
+                            i += 1;
+                        }
+                    }
+                }
+                Token::Str(value)
+            },
+            _ if c.is_ascii_digit() => {
+                let mut number = String::new();
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    number.push(chars[i]);
+                    i += 1;
+                }
+                let mut unit = String::new();
+                while i < chars.len() && chars[i].is_ascii_alphabetic() {
+                    unit.push(chars[i].to_ascii_lowercase());
+                    i += 1;
+                }
+                let value = match number.parse() {
+                    Ok(value) => value,
+                    Err(_) => return error(format!("number too large: {}", number), start)
+                };
+                tokens.push((Token::Number(value, unit), start));
+                continue;
+            },
+            _ if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    ident.push(chars[i]);
+                    i += 1;
+                }
+                tokens.push((Token::Ident(ident), start));
+                continue;
+            },
+            _ => return error(format!("unexpected character '{}'", c), start)
+        };
+
+        tokens.push((token, start));
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EntryType {
+    File,
+    Dir,
+    Symlink,
+    Exec
+}
+
+/// A single comparison, its value converted to the type of the field
+#[derive(Debug)]
+enum Predicate {
+    Name(Operator, Text),
+    Path(Operator, Text),
+    Ext(Operator, Text),
+    Type(bool, EntryType),
+    Size(Operator, u64),
+    /// Compared against the modification time: newer entries are greater
+    Mtime(Operator, SystemTime),
+    Depth(Operator, usize),
+    Owner(bool, u32)
+}
+
+#[derive(Debug)]
+enum Text {
+    Exact(String),
+    Regex(Regex)
+}
+
+impl Text {
+    fn is_match(&self, op: Operator, value: &str) -> bool {
+        match self {
+            Text::Regex(regex) => regex.is_match(value),
+            Text::Exact(text) => (op == Operator::Eq) == (text == value)
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Predicate(Predicate)
+}
+
+/// A recursive descent parser, `||` binding looser than `&&`, and `!` applying to the comparison
+/// or parenthesized expression that follows
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    index: usize,
+    end: usize
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(t, _)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.index).map_or(self.end, |(_, p)| *p)
+    }
+
+    fn next(&mut self) -> Option<(Token, usize)> {
+        let token = self.tokens.get(self.index).cloned();
+        self.index += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.index += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.index += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, QueryError> {
+        let position = self.position();
+        match self.next() {
+            Some((Token::Not, _)) => Ok(Expr::Not(Box::new(self.parse_unary()?))),
+            Some((Token::Open, _)) => {
+                let expr = self.parse_or()?;
+                let position = self.position();
+                match self.next() {
+                    Some((Token::Close, _)) => Ok(expr),
+                    _ => error("expected ')'", position)
+                }
+            },
+            Some((Token::Ident(field), _)) => self.parse_comparison(&field, position),
+            Some(_) => error("expected a field name, '!' or '('", position),
+            None => error("unexpected end of expression", position)
+        }
+    }
+
+    fn parse_comparison(&mut self, field: &str, field_position: usize) -> Result<Expr, QueryError> {
+        let position = self.position();
+        let op = match self.next() {
+            Some((Token::Op(op), _)) => op,
+            _ => return error(format!("expected an operator after '{}'", field), position)
+        };
+        let position = self.position();
+        let value = match self.next() {
+            Some((token @ (Token::Str(_) | Token::Number(..) | Token::Ident(_)), _)) => token,
+            _ => return error(format!("expected a value after '{}'", field), position)
+        };
+
+        check(field, field_position, op, value, position).map(Expr::Predicate)
+    }
+}
+
+/// Type checks a comparison, converting its value to the type of the field
+fn check(field: &str, field_position: usize, op: Operator, value: Token, position: usize) -> Result<Predicate, QueryError> {
+    let text = |value: Token| -> Result<Text, QueryError> {
+        let text = match value {
+            Token::Str(text) => text,
+            _ => return error(format!("'{}' expects a string", field), position)
+        };
+        match op {
+            Operator::Match => Regex::new(&text)
+                .map(Text::Regex)
+                .or_else(|e| error(format!("invalid regex: {}", e), position)),
+            Operator::Eq | Operator::Ne => Ok(Text::Exact(text)),
+            _ => error(format!("'{}' only supports ==, != and ~", field), position)
+        }
+    };
+    let equality = || -> Result<bool, QueryError> {
+        match op {
+            Operator::Eq => Ok(true),
+            Operator::Ne => Ok(false),
+            _ => error(format!("'{}' only supports == and !=", field), position)
+        }
+    };
+    let ordering = || -> Result<Operator, QueryError> {
+        match op {
+            Operator::Match => error(format!("'{}' doesn't support ~", field), position),
+            op => Ok(op)
+        }
+    };
+
+    match field {
+        "name" => Ok(Predicate::Name(op, text(value)?)),
+        "path" => Ok(Predicate::Path(op, text(value)?)),
+        "ext" => Ok(Predicate::Ext(op, text(value)?)),
+        "type" => {
+            let is_equal = equality()?;
+            let entry_type = match &value {
+                Token::Str(v) | Token::Ident(v) => match v.as_str() {
+                    "file" => EntryType::File,
+                    "dir" => EntryType::Dir,
+                    "symlink" => EntryType::Symlink,
+                    "exec" => EntryType::Exec,
+                    _ => return error(format!("unknown type '{}', expected file, dir, symlink or exec", v), position)
+                },
+                _ => return error("'type' expects file, dir, symlink or exec", position)
+            };
+            Ok(Predicate::Type(is_equal, entry_type))
+        },
+        "size" => {
+            let op = ordering()?;
+            match value {
+                Token::Number(n, unit) => Ok(Predicate::Size(op, n.saturating_mul(get_size_unit(&unit, position)?))),
+                _ => error("'size' expects a size, like 50k", position)
+            }
+        },
+        "mtime" => {
+            let op = ordering()?;
+            match value {
+                Token::Number(n, unit) => {
+                    let age = Duration::from_secs(n.saturating_mul(get_duration_unit(&unit, position)?));
+                    let time = SystemTime::now().checked_sub(age).unwrap_or(SystemTime::UNIX_EPOCH);
+                    Ok(Predicate::Mtime(op, time))
+                },
+                _ => error("'mtime' expects an age, like 7d", position)
+            }
+        },
+        "depth" => {
+            let op = ordering()?;
+            match value {
+                Token::Number(n, unit) if unit.is_empty() => Ok(Predicate::Depth(op, n as usize)),
+                _ => error("'depth' expects a number", position)
+            }
+        },
+        "owner" => {
+            let is_equal = equality()?;
+            let uid = match value {
+                Token::Number(n, unit) if unit.is_empty() => n as u32,
+                Token::Str(name) | Token::Ident(name) => match get_user_id(&name) {
+                    Some(uid) => uid,
+                    None => return error(format!("unknown user '{}'", name), position)
+                },
+                _ => return error("'owner' expects a user name or id", position)
+            };
+            Ok(Predicate::Owner(is_equal, uid))
+        },
+        _ => error(format!("unknown field '{}', expected name, path, ext, type, size, mtime, depth or owner", field), field_position)
+    }
+}
+
+fn get_size_unit(unit: &str, position: usize) -> Result<u64, QueryError> {
+    match unit {
+        "" | "b" => Ok(1),
+        "k" | "kb" => Ok(1 << 10),
+        "m" | "mb" => Ok(1 << 20),
+        "g" | "gb" => Ok(1 << 30),
+        "t" | "tb" => Ok(1 << 40),
+        _ => error(format!("unknown size unit '{}', expected b, k, m, g or t", unit), position)
+    }
+}
+
+fn get_duration_unit(unit: &str, position: usize) -> Result<u64, QueryError> {
+    match unit {
+        "s" => Ok(1),
+        "m" | "min" => Ok(60),
+        "h" => Ok(60 * 60),
+        "d" => Ok(24 * 60 * 60),
+        "w" => Ok(7 * 24 * 60 * 60),
+        "" => error("missing duration unit, expected s, m, h, d or w", position),
+        _ => error(format!("unknown duration unit '{}', expected s, m, h, d or w", unit), position)
+    }
+}
+
+/// An entry being evaluated, its metadata only read when a predicate needs it
+pub struct Entry<'a> {
+    path: &'a Path,
+    relative_path: &'a str,
+    depth: usize,
+    /// The metadata known ahead, the only one of the entries of a revision as they aren't checked out
+    meta: Option<&'a EntryMeta>,
+    metadata: OnceCell<Option<Metadata>>
+}
+
+impl<'a> Entry<'a> {
+    pub fn new(path: &'a Path, relative_path: &'a str, depth: usize, meta: Option<&'a EntryMeta>) -> Self {
+        Entry { path, relative_path, depth, meta, metadata: OnceCell::new() }
+    }
+
+    fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.get_or_init(|| fs::symlink_metadata(self.path).ok()).as_ref()
+    }
+
+    fn size(&self) -> Option<u64> {
+        match self.meta {
+            Some(meta) => Some(meta.size),
+            None => self.metadata().map(|m| m.len())
+        }
+    }
+
+    fn mtime(&self) -> Option<SystemTime> {
+        match self.meta {
+            Some(meta) => meta.mtime,
+            None => self.metadata().and_then(|m| m.modified().ok())
+        }
+    }
+
+    fn owner(&self) -> Option<u32> {
+        match self.meta {
+            Some(meta) => meta.uid,
+            None => self.metadata().and_then(get_owner)
+        }
+    }
+
+    fn is_type(&self, entry_type: EntryType) -> bool {
+        // Git records the same file types as unix, e.g. `100755` for executables
+        if let Some(mode) = self.meta.and_then(|m| m.mode) {
+            let file_type = mode & 0o170000;
+            return match entry_type {
+                EntryType::File => file_type == 0o100000,
+                EntryType::Dir => file_type == 0o040000,
+                EntryType::Symlink => file_type == 0o120000,
+                EntryType::Exec => file_type == 0o100000 && mode & 0o111 != 0
+            };
+        }
+
+        let Some(metadata) = self.metadata() else { return false };
+        let file_type = metadata.file_type();
+        match entry_type {
+            EntryType::File => file_type.is_file(),
+            EntryType::Dir => file_type.is_dir(),
+            EntryType::Symlink => file_type.is_symlink(),
+            EntryType::Exec => file_type.is_file() && is_executable(metadata)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &Metadata) -> bool {
+    false
+}
+
+impl Expr {
+    fn eval(&self, entry: &Entry) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(entry) && b.eval(entry),
+            Expr::Or(a, b) => a.eval(entry) || b.eval(entry),
+            Expr::Not(e) => !e.eval(entry),
+            Expr::Predicate(p) => p.eval(entry)
+        }
+    }
+}
+
+impl Predicate {
+    /// Entries whose metadata can't be read never match a predicate that needs it
+    fn eval(&self, entry: &Entry) -> bool {
+        match self {
+            Predicate::Name(op, text) => {
+                let name = entry.path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+                text.is_match(*op, &name)
+            },
+            Predicate::Path(op, text) => text.is_match(*op, entry.relative_path),
+            Predicate::Ext(op, text) => {
+                let ext = entry.path.extension().map(|e| e.to_string_lossy()).unwrap_or_default();
+                text.is_match(*op, &ext)
+            },
+            Predicate::Type(is_equal, entry_type) => entry.is_type(*entry_type) == *is_equal,
+            Predicate::Size(op, size) => entry.size().is_some_and(|s| op.compare(s, *size)),
+            Predicate::Mtime(op, time) => entry.mtime().is_some_and(|t| op.compare(t, *time)),
+            Predicate::Depth(op, depth) => op.compare(entry.depth, *depth),
+            Predicate::Owner(is_equal, uid) => entry.owner()
+                .is_some_and(|o| (o == *uid) == *is_equal)
+        }
+    }
+}
+
+/// A parsed and type checked `--where` expression
+#[derive(Debug)]
+pub struct Query {
+    expr: Expr
+}
+
+impl Query {
+    pub fn parse(source: &str) -> Result<Self, QueryError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, index: 0, end: source.chars().count() };
+        let expr = parser.parse_or()?;
+        if parser.index < parser.tokens.len() {
+            return error("expected '&&' or '||'", parser.position());
+        }
+        Ok(Query { expr })
+    }
+
+    pub fn is_match(&self, entry: &Entry) -> bool {
+        self.expr.eval(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(query: &str, path: &str, depth: usize) -> bool {
+        let query = Query::parse(query).unwrap();
+        query.is_match(&Entry::new(Path::new(path), path, depth, None))
+    }
+
+    #[test]
+    fn test_eval() {
+        assert!(is_match(r#"ext == "rs" && !path ~ "tests/""#, "src/main.rs", 2));
+        assert!(!is_match(r#"ext == "rs" && !path ~ "tests/""#, "tests/main.rs", 2));
+        assert!(is_match(r#"name == "a.txt" || depth >= 3"#, "x/y/z.md", 3));
+        assert!(!is_match(r#"!(name != "a.txt") && depth < 2"#, "a.txt", 2));
+        assert!(is_match("type == dir && size > 0", "src", 1));
+        assert!(is_match("mtime > 100w && type != symlink", "Cargo.toml", 1));
+        assert!(!is_match("size > 1g", "Cargo.toml", 1));
+    }
+
+    #[test]
+    fn test_errors() {
+        let message = |query: &str| Query::parse(query).unwrap_err().to_string();
+
+        assert_eq!(message("colour == \"red\""), "unknown field 'colour', expected name, path, ext, type, size, mtime, depth or owner (at character 1)");
+        assert_eq!(message("size > \"big\""), "'size' expects a size, like 50k (at character 8)");
+        assert_eq!(message("mtime > 7"), "missing duration unit, expected s, m, h, d or w (at character 9)");
+        assert_eq!(message("type > dir"), "'type' only supports == and != (at character 8)");
+        assert_eq!(message("ext == \"rs\" size > 5"), "expected '&&' or '||' (at character 13)");
+        assert_eq!(message("(depth < 2"), "expected ')' (at character 11)");
+        assert_eq!(message("name == \"a"), "unterminated string (at character 9)");
+        assert!(message("name ~ \"(\"").starts_with("invalid regex"));
+    }
+}
@@ -1,29 +1,179 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::io::{BufWriter, StdoutLock, Write};
-use std::path::PathBuf;
-use crate::hex_to_color;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use ansi_term::{Color, Style};
+use crate::{DirRenderItem, ErrorRenderItem, FileRenderItem, hex_to_color};
+use crate::crawler::{LinkInfo, Summary};
+use crate::git::{Churn, GitChange, GitStatus, LastCommit};
+use crate::metadata::{EntryMeta, format_permissions, format_size, format_time, get_size_width, get_time_width, load_user_names};
 use crate::settings::Config;
 
+/// Width of the owner column, longer names overflowing it
+const OWNER_WIDTH: usize = 8;
+/// Width of the commit author, longer names being cut
+const AUTHOR_WIDTH: usize = 16;
+/// Length of the abbreviated commit hashes
+const HASH_LENGTH: usize = 7;
+
 pub struct Renderer<'a, 'b> {
     pub config: &'a Config,
-    pub writer: BufWriter<StdoutLock<'b>>
+    pub writer: BufWriter<StdoutLock<'b>>,
+    /// Only loaded when showing the owner column
+    user_names: HashMap<u32, String>,
+    /// Relative times are all computed from the start
+    now: SystemTime,
+    /// The entries that changed since the previous frame of `--watch`
+    highlighted: HashSet<PathBuf>
 }
 
 impl<'a, 'b> Renderer<'a, 'b> {
-    pub(crate) fn new(config: &'a Config) -> Self {
+    pub(crate) fn new(config: &'a Config, highlighted: HashSet<PathBuf>) -> Self {
         let stdout = std::io::stdout();
         let writer = BufWriter::new(stdout.lock());
-        Renderer {config, writer}
+        let user_names = if config.has_owner_column() { load_user_names() } else { HashMap::new() };
+        Renderer {config, writer, user_names, now: SystemTime::now(), highlighted}
     }
 
-    pub fn render_file(&mut self, path: &PathBuf) {
-        let filename_os = path.file_name().unwrap();
-        let filename = filename_os.to_str().unwrap();
+    /// The size column shows the total of the tree with `--du`, the columns of a revision only its commit
+    pub fn render_root(&mut self, path: &Path, summary: Option<&Summary>) {
+        let du_size = summary.and_then(|s| s.size).filter(|_| self.config.args.du);
+        if self.config.has_columns() {
+            let is_checked_out = self.config.tree.as_ref().is_none_or(|t| t.is_checked_out);
+            let mut meta = is_checked_out.then(|| fs::metadata(path).ok().map(|m| EntryMeta::new(&m))).flatten();
+            if let (Some(meta), Some(size)) = (meta.as_mut(), du_size) {
+                meta.size = size;
+            }
+            self.render_columns(meta.as_ref(), self.config.get_last_dir_commit(path));
+        }
+        write!(&mut self.writer, "{}", path.display()).unwrap();
+        // The tree doesn't come from the file system, e.g. `@ v1.2` or `@ main..HEAD`
+        if let Some(rev) = self.config.args.rev.as_ref().or(self.config.args.rev_diff.as_ref()) {
+            write!(&mut self.writer, " {}", Style::new().dimmed().paint(format!("@ {}", rev))).unwrap();
+        }
+        if let Some(count) = summary.and_then(|s| s.root_files_count) {
+            self.render_files_count(count);
+        }
+        self.render_dir_size(du_size);
+        writeln!(&mut self.writer).unwrap();
+    }
+
+    /// Renders the enabled metadata columns, or blank ones for the lines without any metadata
+    pub fn render_columns(&mut self, meta: Option<&EntryMeta>, commit: Option<&LastCommit>) {
+        let style = Style::new().dimmed();
+        if self.config.has_perms_column() {
+            let value = meta.map_or(String::new(), |m| m.mode.map_or("-".repeat(10), format_permissions));
+            write!(&mut self.writer, "{} ", style.paint(format!("{:<10}", value))).unwrap();
+        }
+        if self.config.has_owner_column() {
+            let value = meta.map_or(String::new(), |m| match m.uid {
+                Some(uid) => self.user_names.get(&uid).cloned().unwrap_or_else(|| uid.to_string()),
+                None => "-".to_string()
+            });
+            write!(&mut self.writer, "{} ", style.paint(format!("{:<width$}", value, width = OWNER_WIDTH))).unwrap();
+        }
+        if self.config.has_size_column() {
+            let format = self.config.args.size_format;
+            let value = meta.map_or(String::new(), |m| format_size(m.size, format));
+            write!(&mut self.writer, "{} ", style.paint(format!("{:>width$}", value, width = get_size_width(format)))).unwrap();
+        }
+        if self.config.has_mtime_column() {
+            let format = self.config.args.time_format;
+            let value = meta.and_then(|m| m.mtime).map_or(String::new(), |t| format_time(t, format, self.now));
+            write!(&mut self.writer, "{} ", style.paint(format!("{:<width$}", value, width = get_time_width(format)))).unwrap();
+        }
+        if self.config.has_commit_column() {
+            self.render_commit(commit);
+        }
+    }
+
+    /// Renders the date, short hash and author of a commit, e.g. `2024-03-02 14:10 3f2a9c1 Jane Doe`
+    fn render_commit(&mut self, commit: Option<&LastCommit>) {
+        let format = self.config.args.time_format;
+        let time_width = get_time_width(format);
+        let Some(commit) = commit else {
+            let width = time_width + HASH_LENGTH + AUTHOR_WIDTH + 2;
+            write!(&mut self.writer, "{:width$} ", "", width = width).unwrap();
+            return;
+        };
+
+        let time = format_time(commit.time, format, self.now);
+        let hash = commit.hash.get(..HASH_LENGTH).unwrap_or(&commit.hash);
+        let author = match commit.author.chars().count() > AUTHOR_WIDTH {
+            true => format!("{}…", commit.author.chars().take(AUTHOR_WIDTH - 1).collect::<String>()),
+            false => commit.author.clone()
+        };
+        write!(&mut self.writer, "{} {} {} ",
+            Style::new().dimmed().paint(format!("{:<width$}", time, width = time_width)),
+            Color::Yellow.paint(hash),
+            Style::new().dimmed().paint(format!("{:<width$}", author, width = AUTHOR_WIDTH))).unwrap();
+    }
+
+    pub fn render_file(&mut self, file: &FileRenderItem) {
+        let filename_os = file.path.file_name().unwrap();
+        let filename = &*filename_os.to_string_lossy();
+
+        if let Some(link) = &file.link {
+            let glyph = self.config.get_file_link_glyph();
+            let style = hex_to_color(&self.config.colors.files.symlink).normal();
+            write!(&mut self.writer, "{} {}", style.paint(glyph), get_name_style(style, file.change, self.highlighted.contains(&file.path)).paint(filename)).unwrap();
+            self.render_link_target(link);
+            self.render_git_status(file.git);
+            self.render_git_change(file.change);
+            writeln!(&mut self.writer).unwrap();
+            return;
+        }
 
         let glyph = self.config.get_associated_file_glyph(filename);
-        let color = self.config.get_associated_file_color(filename);
-        let style = hex_to_color(color).normal();
+        let churn = self.config.get_file_churn(&file.path);
+        let style = match churn {
+            Some(churn) => self.get_churn_color(churn).normal(),
+            None => hex_to_color(self.config.get_associated_file_color(filename)).normal()
+        };
+
+        write!(&mut self.writer, "{} {}", style.paint(glyph), get_name_style(style, file.change, self.highlighted.contains(&file.path)).paint(filename)).unwrap();
+        if let Some(count) = file.matches_count {
+            let value = format!("({} {})", count, if count == 1 { "match" } else { "matches" });
+            write!(&mut self.writer, " {}", Style::new().dimmed().paint(value)).unwrap();
+        }
+        self.render_git_status(file.git);
+        self.render_git_change(file.change);
+        self.render_churn(churn);
+        writeln!(&mut self.writer).unwrap();
+    }
 
-        write!(&mut self.writer, "{} {}\n", style.paint(glyph), style.paint(filename)).unwrap();
+    /// Renders a short badge for the entries that differ from the last commit, or are ignored
+    fn render_git_status(&mut self, status: Option<GitStatus>) {
+        let Some(status) = status else { return };
+        let (badge, style) = match status {
+            GitStatus::Conflicted => ("U", Color::Red.bold()),
+            GitStatus::Modified => ("M", Color::Yellow.normal()),
+            GitStatus::Renamed => ("R", Color::Blue.normal()),
+            GitStatus::Staged => ("S", Color::Green.normal()),
+            GitStatus::Untracked => ("?", Color::Purple.normal()),
+            GitStatus::Ignored => ("!", Style::new().dimmed())
+        };
+        write!(&mut self.writer, " {}", style.paint(format!("[{}]", badge))).unwrap();
+    }
+
+    /// Renders a short badge for the entries that changed between the revisions of `--rev-diff`
+    fn render_git_change(&mut self, change: Option<GitChange>) {
+        let Some(change) = change else { return };
+        let (badge, style) = match change {
+            GitChange::Added => ("A", Color::Green.normal()),
+            GitChange::Removed => ("D", Color::Red.normal()),
+            GitChange::Modified => ("M", Color::Yellow.normal()),
+            GitChange::Renamed => ("R", Color::Blue.normal())
+        };
+        write!(&mut self.writer, " {}", style.paint(format!("[{}]", badge))).unwrap();
+    }
+
+    fn render_link_target(&mut self, link: &LinkInfo) {
+        write!(&mut self.writer, " -> {}", link.target.display()).unwrap();
+        if link.is_broken {
+            write!(&mut self.writer, " {}", Color::Red.paint("[broken]")).unwrap();
+        }
     }
 
     pub fn render_pipe_v(&mut self) {
@@ -39,34 +189,206 @@ impl<'a, 'b> Renderer<'a, 'b> {
     }
 
     pub fn render_pipe_e(&mut self) {
-        write!(&mut self.writer, "{} ", self.config.glyphs.get("pipe-e").unwrap()).unwrap();
+        write!(&mut self.writer, "{}", self.config.glyphs.get("pipe-e").unwrap()).unwrap();
+    }
+
+    pub fn render_indent(&mut self) {
+        write!(&mut self.writer, "  ").unwrap();
     }
 
-    pub fn render_dir(&mut self, path: &PathBuf, ignored: bool) {
-        let filename_os = path.file_name().unwrap();
-        let filename = filename_os.to_str().unwrap();
+    pub fn render_dir(&mut self, dir: &DirRenderItem) {
+        let filename_os = dir.path.file_name().unwrap();
+        let filename = &*filename_os.to_string_lossy();
+
+        if let Some(link) = &dir.link {
+            let glyph = self.config.get_dir_link_glyph();
+            let style = hex_to_color(&self.config.colors.directories.symlink).normal();
+            write!(&mut self.writer, "{} {}", style.paint(glyph), get_name_style(style, dir.change, self.highlighted.contains(&dir.path)).paint(filename)).unwrap();
+            self.render_link_target(link);
+            self.render_git_status(dir.git);
+            self.render_git_change(dir.change);
+            if dir.is_recursive {
+                write!(&mut self.writer, " {}", Color::Red.paint("[recursive, not followed]")).unwrap();
+            } else if dir.is_truncated {
+                write!(&mut self.writer, "{}", style.paint("/…")).unwrap();
+            }
+            writeln!(&mut self.writer).unwrap();
+            return;
+        }
 
-        let glyph = self.config.get_associated_dir_glyph(filename);
+        let glyph = match (&dir.mount, &dir.repo) {
+            (Some(_), _) => self.config.get_mount_glyph(),
+            (None, Some(_)) => self.config.get_repository_glyph(),
+            (None, None) => self.config.get_associated_dir_glyph(filename)
+        };
 
-        if ignored {
+        if dir.is_ignored {
             let color = &self.config.colors.directories.ignored;
             let style = hex_to_color(color).normal();
-            write!(&mut self.writer, "{} {}{}\n", style.paint(glyph), style.paint(filename), style.paint("/...")).unwrap();
+            write!(&mut self.writer, "{} {}{}", style.paint(glyph), get_name_style(style, dir.change, self.highlighted.contains(&dir.path)).paint(filename), style.paint("/...")).unwrap();
+            self.render_mount(dir);
+            self.render_repo(dir);
+            self.render_git_status(dir.git);
+            self.render_git_change(dir.change);
+            self.render_dir_size(dir.size);
+            writeln!(&mut self.writer).unwrap();
+
+        } else if dir.is_truncated {
+            let style = self.get_dir_style(dir, filename);
+            write!(&mut self.writer, "{} {}{}", style.paint(glyph), get_name_style(style, dir.change, self.highlighted.contains(&dir.path)).paint(filename), style.paint("/…")).unwrap();
+            self.render_mount(dir);
+            self.render_repo(dir);
+            self.render_git_status(dir.git);
+            self.render_git_change(dir.change);
+            self.render_churn(self.config.get_dir_churn(&dir.path));
+            self.render_dir_size(dir.size);
+            writeln!(&mut self.writer).unwrap();
 
         } else {
-            let color = self.config.get_associated_dir_color(filename);
-            let style = hex_to_color(color).normal();
-            write!(&mut self.writer, "{} {}\n", style.paint(glyph), style.paint(filename)).unwrap();
+            let style = self.get_dir_style(dir, filename);
+            write!(&mut self.writer, "{} {}", style.paint(glyph), get_name_style(style, dir.change, self.highlighted.contains(&dir.path)).paint(filename)).unwrap();
+            self.render_mount(dir);
+            self.render_repo(dir);
+            self.render_git_status(dir.git);
+            self.render_git_change(dir.change);
+            self.render_churn(self.config.get_dir_churn(&dir.path));
+            if let Some(count) = dir.files_count {
+                self.render_files_count(count);
+            }
+            self.render_dir_size(dir.size);
+            writeln!(&mut self.writer).unwrap();
+        }
+    }
+
+    /// Mount points keep their color with `--churn`, unlike repositories
+    fn get_dir_style(&self, dir: &DirRenderItem, filename: &str) -> Style {
+        let color = match (&dir.mount, self.config.get_dir_churn(&dir.path), &dir.repo) {
+            (Some(_), _, _) => &self.config.colors.directories.mount,
+            (None, Some(churn), _) => return self.get_churn_color(churn).normal(),
+            (None, None, Some(_)) => &self.config.colors.directories.repository,
+            (None, None, None) => self.config.get_associated_dir_color(filename)
+        };
+        hex_to_color(color).normal()
+    }
+
+    /// Blends the colors of the churn gradient, the most changed entries getting the last one
+    fn get_churn_color(&self, churn: Churn) -> Color {
+        let stops = &self.config.colors.churn;
+        if stops.len() < 2 {
+            return stops.first().map_or(Color::White, |c| hex_to_color(c));
+        }
+
+        let position = churn.ratio.clamp(0.0, 1.0) * (stops.len() - 1) as f64;
+        let index = (position.floor() as usize).min(stops.len() - 2);
+        let t = position - index as f64;
+        match (hex_to_color(&stops[index]), hex_to_color(&stops[index + 1])) {
+            (Color::RGB(r1, g1, b1), Color::RGB(r2, g2, b2)) => {
+                let blend = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+                Color::RGB(blend(r1, r2), blend(g1, g2), blend(b1, b2))
+            },
+            (from, _) => from
+        }
+    }
+
+    /// Shows the number of commits with `--churn-counts`
+    fn render_churn(&mut self, churn: Option<Churn>) {
+        if let (Some(churn), true) = (churn, self.config.args.churn_counts) {
+            let value = format!("({} {})", churn.commits, if churn.commits == 1 { "commit" } else { "commits" });
+            write!(&mut self.writer, " {}", Style::new().dimmed().paint(value)).unwrap();
+        }
+    }
+
+    /// Shows the file system type of mount points, e.g. `[nfs4]`
+    fn render_mount(&mut self, dir: &DirRenderItem) {
+        if let Some(fs_type) = &dir.mount {
+            let style = hex_to_color(&self.config.colors.directories.mount).normal();
+            write!(&mut self.writer, " {}", style.paint(format!("[{}]", fs_type))).unwrap();
+        }
+    }
+
+    /// Shows the current branch of nested repositories, followed by a star when there are uncommitted
+    /// changes, e.g. `[main*]`
+    fn render_repo(&mut self, dir: &DirRenderItem) {
+        if let Some(repo) = &dir.repo {
+            let style = hex_to_color(&self.config.colors.directories.repository).normal();
+            let value = format!("[{}{}]", repo.head, if repo.is_dirty { "*" } else { "" });
+            write!(&mut self.writer, " {}", style.paint(value)).unwrap();
+        }
+    }
+
+    /// Shows the total size next to the name, unless it's already in the size column
+    fn render_files_count(&mut self, count: usize) {
+        let value = format!("({} {})", count, if count == 1 { "file" } else { "files" });
+        write!(&mut self.writer, " {}", Style::new().dimmed().paint(value)).unwrap();
+    }
+
+    fn render_dir_size(&mut self, size: Option<u64>) {
+        if let (Some(size), false) = (size, self.config.has_size_column()) {
+            let value = format!("({})", format_size(size, self.config.args.size_format));
+            write!(&mut self.writer, " {}", Style::new().dimmed().paint(value)).unwrap();
         }
     }
 
-    pub fn render_skippedfiles(&mut self, ext: &String, count: i32) {
+    pub fn render_skippedfiles(&mut self, ext: &str, count: i32) {
         let glyph = self.config.get_associated_ext_glyph(ext);
 
         let color = self.config.get_associated_ext_color(ext);
         let style = hex_to_color(color).normal();
 
         let value = format!("{} {} files...", count, ext);
-        write!(&mut self.writer, "{} {}\n", style.paint(glyph), style.paint(value)).unwrap();
+        writeln!(&mut self.writer, "{} {}", style.paint(glyph), style.paint(value)).unwrap();
+    }
+
+    pub fn render_error(&mut self, error: &ErrorRenderItem) {
+        let glyph = self.config.glyphs.get("nf-fa-warning").unwrap();
+        let style = Color::Red.normal();
+
+        let filename = error.path.file_name().map_or(error.path.to_string_lossy(), |f| f.to_string_lossy());
+        let separator = if error.is_dir { "/" } else { "" };
+        let value = format!("{}{} [{}]", filename, separator, error.error.kind());
+        writeln!(&mut self.writer, "{} {}", style.paint(glyph), style.paint(value)).unwrap();
+    }
+
+    /// Renders the closing line, e.g. `4 directories, 12 files`, the other counts only when non zero
+    pub fn render_summary(&mut self, summary: &Summary) {
+        let plural = |count: usize, singular: &str, plural: &str| {
+            format!("{} {}", count, if count == 1 { singular } else { plural })
+        };
+
+        // Files aren't listed with `--dirs-only`, the directories count theirs with `--file-counts`
+        let mut parts = vec![plural(summary.dirs_count, "directory", "directories")];
+        if !self.config.args.dirs_only {
+            parts.push(plural(summary.files_count, "file", "files"));
+        }
+        let optional_counts = vec![
+            (summary.links_count, plural(summary.links_count, "symlink", "symlinks")),
+            (summary.hidden_count, format!("{} hidden", summary.hidden_count)),
+            (summary.ignored_count, format!("{} ignored", summary.ignored_count)),
+            (summary.filtered_count, format!("{} filtered out", summary.filtered_count)),
+            (summary.errors_count, format!("{} unreadable", summary.errors_count))
+        ];
+        parts.extend(optional_counts.into_iter().filter(|(c, _)| *c > 0).map(|(_, part)| part));
+
+        let mut value = parts.join(", ");
+        if let Some(size) = summary.size {
+            value = format!("{}, {} total", value, format_size(size, self.config.args.size_format));
+        }
+        writeln!(&mut self.writer, "\n{}", value).unwrap();
+    }
+
+    pub fn render_hidden(&mut self, count: usize) {
+        let glyph = self.config.glyphs.get("nf-fa-eye_slash").unwrap();
+        let style = Style::new().dimmed();
+        let value = format!("{} hidden {}...", count, if count == 1 { "entry" } else { "entries" });
+        writeln!(&mut self.writer, "{} {}", style.paint(glyph), style.paint(value)).unwrap();
+    }
+}
+
+/// Removed entries are struck through, the ones that just changed are highlighted
+fn get_name_style(style: Style, change: Option<GitChange>, is_highlighted: bool) -> Style {
+    match (change, is_highlighted) {
+        (_, true) => style.reverse(),
+        (Some(GitChange::Removed), false) => style.strikethrough(),
+        (_, false) => style
     }
 }
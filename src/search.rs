@@ -0,0 +1,58 @@
+use std::fs;
+use std::path::Path;
+use regex::bytes::{Regex, RegexBuilder};
+
+/// Number of leading bytes looked at to tell binary files apart, like git does
+const BINARY_CHECK_LENGTH: usize = 8000;
+
+/// The `--contains` pattern, matched against the raw content of the files
+pub struct Search {
+    regex: Regex
+}
+
+impl Search {
+    pub fn new(pattern: &str, is_regex: bool, ignore_case: bool) -> Result<Self, regex::Error> {
+        let pattern = if is_regex { pattern.to_string() } else { regex::escape(pattern) };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(ignore_case)
+            .multi_line(true)
+            .build()?;
+        Ok(Search { regex })
+    }
+
+    /// The number of matches in a file, `None` when it's binary or can't be read
+    pub fn count_matches(&self, path: &Path) -> Option<usize> {
+        let content = fs::read(path).ok()?;
+        if is_binary(&content) {
+            return None;
+        }
+        Some(self.regex.find_iter(&content).count())
+    }
+}
+
+fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(BINARY_CHECK_LENGTH)].contains(&0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_matches() {
+        let dir = std::env::temp_dir().join(format!("rusty-tree-search-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("client.rs"), "let c = FooClient::new();\n// fooclient.call()\n").unwrap();
+        fs::write(dir.join("client.bin"), b"FooClient\0\x01\x02").unwrap();
+
+        let literal = Search::new("FooClient::", false, false).unwrap();
+        assert_eq!(literal.count_matches(&dir.join("client.rs")), Some(1));
+        assert_eq!(literal.count_matches(&dir.join("client.bin")), None);
+        assert_eq!(literal.count_matches(&dir.join("missing.rs")), None);
+
+        let regex = Search::new(r"^\W*foo\w+", true, true).unwrap();
+        assert_eq!(regex.count_matches(&dir.join("client.rs")), Some(1));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,19 +3,29 @@ use std::fs::File;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use clap::Parser;
-use glob_match::glob_match;
+use clap::{CommandFactory, Parser, ValueEnum};
+use clap::error::ErrorKind;
 use serde_derive::Deserialize;
+use crate::filter::Filter;
+use crate::query::{Entry, Query};
+use crate::search::Search;
+use crate::metadata::EntryMeta;
+use crate::mounts::Mounts;
+use crate::git::{Churn, GitChange, GitChurn, GitHistory, GitStatus, GitStatuses, GitTree, LastCommit};
 
 #[derive(Deserialize, Debug, Clone)]
+#[allow(dead_code)]
 pub struct DirectoryIconSet {
     pub default: String,
     pub symlink: String,
     pub junction: String,
+    pub mount: String,
+    pub repository: String,
     pub wellknown: HashMap<String, String>
 }
 
 #[derive(Deserialize, Debug, Clone)]
+#[allow(dead_code)]
 pub struct FileIconSet {
     pub default: String,
     pub symlink: String,
@@ -32,15 +42,19 @@ pub struct IconSet {
 }
 
 #[derive(Deserialize, Debug, Clone)]
+#[allow(dead_code)]
 pub struct DirectoryColorSet {
     pub default: String,
     pub ignored: String,
     pub symlink: String,
     pub junction: String,
+    pub mount: String,
+    pub repository: String,
     pub wellknown: HashMap<String, String>
 }
 
 #[derive(Deserialize, Debug, Clone)]
+#[allow(dead_code)]
 pub struct FileColorSet {
     pub default: String,
     pub symlink: String,
@@ -53,10 +67,63 @@ pub struct FileColorSet {
 #[derive(Deserialize, Debug, Clone)]
 pub struct ColorSet {
     pub directories: DirectoryColorSet,
-    pub files: FileColorSet
+    pub files: FileColorSet,
+    /// The gradient of `--churn`, from the least to the most changed entries
+    pub churn: Vec<String>
 }
 
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum SortMode {
+    /// By file name
+    Name,
+    /// By file name, numbers compared by value
+    Natural,
+    /// By extension, then by file name
+    Extension,
+    /// Largest first
+    Size,
+    /// Most recently modified first
+    Mtime,
+    /// As returned by the file system
+    None
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum GroupMode {
+    DirsFirst,
+    FilesFirst,
+    Mixed
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum SizeFormat {
+    /// Exact number of bytes
+    Bytes,
+    /// Powers of 1024
+    Iec,
+    /// Powers of 1000
+    Si
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum DuMode {
+    /// Sum of the file sizes
+    Apparent,
+    /// Space allocated on disk
+    Blocks
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum TimeFormat {
+    /// Date and time, to the minute
+    Absolute,
+    /// Age, e.g. `3d ago`
+    Relative,
+    /// ISO 8601, to the second
+    Iso
+}
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 pub struct Args {
@@ -67,8 +134,172 @@ pub struct Args {
     #[arg(short, long, default_value_t = false)]
     pub unfold: bool,
 
-    #[arg(short, long)]
-    pub filter: Option<String>,
+    /// Only show the files matching this pattern, or within a matching directory. Patterns
+    /// containing a `/` match the path relative to the root, the others the file name.
+    #[arg(short = 'f', long, visible_alias = "filter")]
+    pub include: Vec<String>,
+
+    /// Leave out the entries matching this pattern
+    #[arg(short = 'x', long)]
+    pub exclude: Vec<String>,
+
+    /// Use regular expressions matching the path relative to the root instead of globs, and a
+    /// regular expression for `--contains`
+    #[arg(long, default_value_t = false)]
+    pub regex: bool,
+
+    /// Only show the text files containing this pattern
+    #[arg(long, value_name = "PATTERN")]
+    pub contains: Option<String>,
+
+    /// Show the number of matches next to the files found with `--contains`
+    #[arg(long, default_value_t = false, requires = "contains")]
+    pub count_matches: bool,
+
+    /// Match the patterns case insensitively
+    #[arg(short, long, default_value_t = false)]
+    pub ignore_case: bool,
+
+    /// Only show the entries matching this expression, e.g. `ext == "rs" && size > 50k && mtime > 7d`.
+    /// Fields: name, path, ext, type (file, dir, symlink, exec), size, mtime, depth and owner
+    #[arg(short = 'w', long = "where", value_name = "EXPR")]
+    pub query: Option<String>,
+
+    /// Descend at most this many directory levels
+    #[arg(short = 'L', long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub level: Option<usize>,
+
+    /// Number of threads reading directories, defaults to the available parallelism
+    #[arg(short, long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub threads: Option<usize>,
+
+    /// Descend into directories pointed by symbolic links
+    #[arg(long, default_value_t = false)]
+    pub follow: bool,
+
+    /// Show the entries excluded by .gitignore, .ignore and .rustytreeignore files
+    #[arg(long, default_value_t = false)]
+    pub no_ignore: bool,
+
+    /// Order of the entries within a directory
+    #[arg(short, long, value_enum, default_value_t = SortMode::Name)]
+    pub sort: SortMode,
+
+    /// Reverse the sort order
+    #[arg(short, long, default_value_t = false)]
+    pub reverse: bool,
+
+    /// Where directories are listed relative to files
+    #[arg(short, long, value_enum, default_value_t = GroupMode::FilesFirst)]
+    pub group: GroupMode,
+
+    /// Show dotfiles
+    #[arg(short, long, default_value_t = false)]
+    pub all: bool,
+
+    /// List directories only
+    #[arg(short, long, default_value_t = false)]
+    pub dirs_only: bool,
+
+    /// Show the number of files next to each directory
+    #[arg(long, default_value_t = false, requires = "dirs_only")]
+    pub file_counts: bool,
+
+    /// Remove the directories left empty, the default when filtering
+    #[arg(long, default_value_t = false, overrides_with = "no_prune")]
+    pub prune: bool,
+
+    /// Keep the directories left empty
+    #[arg(long, default_value_t = false, overrides_with = "prune")]
+    pub no_prune: bool,
+
+    /// Don't descend into directories on other file systems than the root's
+    #[arg(long, default_value_t = false)]
+    pub one_file_system: bool,
+
+    /// Show the size of the entries
+    #[arg(long, default_value_t = false)]
+    pub size: bool,
+
+    /// Show the modification time of the entries
+    #[arg(long, default_value_t = false)]
+    pub mtime: bool,
+
+    /// Show the permissions of the entries
+    #[arg(long, default_value_t = false)]
+    pub perms: bool,
+
+    /// Show the owner of the entries
+    #[arg(long, default_value_t = false)]
+    pub owner: bool,
+
+    /// Show all the metadata columns: permissions, owner, size and modification time
+    #[arg(short, long, default_value_t = false)]
+    pub long: bool,
+
+    /// How sizes are shown
+    #[arg(long, value_enum, default_value_t = SizeFormat::Iec)]
+    pub size_format: SizeFormat,
+
+    /// Show the total size of each directory, including the entries that aren't shown
+    #[arg(long, default_value_t = false)]
+    pub du: bool,
+
+    /// What the sizes of `--du` add up
+    #[arg(long, value_enum, default_value_t = DuMode::Apparent, requires = "du")]
+    pub du_mode: DuMode,
+
+    /// How modification times are shown
+    #[arg(long, value_enum, default_value_t = TimeFormat::Absolute)]
+    pub time_format: TimeFormat,
+
+    /// Show the git status of the entries: modified, staged, renamed, untracked, ignored or conflicted
+    #[arg(long, default_value_t = false)]
+    pub git: bool,
+
+    /// Only show the entries that differ from the last commit, along with their status
+    #[arg(long, default_value_t = false)]
+    pub git_changed_only: bool,
+
+    /// Only show the files tracked by git, as listed by its index rather than read from the file system
+    #[arg(long, default_value_t = false)]
+    pub tracked: bool,
+
+    /// Show the tree of a git revision rather than the files on disk, e.g. `v1.2` or `HEAD~3`
+    #[arg(long, value_name = "COMMIT", conflicts_with_all = ["tracked", "git", "git_changed_only", "du", "contains"])]
+    pub rev: Option<String>,
+
+    /// Show the tree of the last revision of a range, e.g. `main..HEAD`, marking the entries added, removed,
+    /// modified or renamed since the first one
+    #[arg(long, value_name = "RANGE", conflicts_with_all = ["rev", "tracked", "git", "git_changed_only", "du", "contains"])]
+    pub rev_diff: Option<String>,
+
+    /// Show the date, hash and author of the last commit of each file, and the most recent one under each
+    /// directory
+    #[arg(long, default_value_t = false)]
+    pub git_blame_summary: bool,
+
+    /// Color the entries by the number of commits that touched them, over the whole history or since a
+    /// date git understands, e.g. `--churn=3.months`
+    #[arg(long, value_name = "SINCE", num_args = 0..=1, require_equals = true)]
+    pub churn: Option<Option<String>>,
+
+    /// Show the number of commits next to the entries colored by `--churn`
+    #[arg(long, default_value_t = false, requires = "churn")]
+    pub churn_counts: bool,
+
+    /// Collapse the nested git repositories and submodules like ignored directories
+    #[arg(long, default_value_t = false)]
+    pub no_nested_repos: bool,
+
+    /// Render the tree again whenever the directories it was read from change, highlighting the entries
+    /// that changed. Only available on Linux
+    #[arg(long, default_value_t = false, conflicts_with_all = ["rev", "rev_diff"])]
+    pub watch: bool,
+
+    /// Don't print the summary at the end of the tree
+    #[arg(long, default_value_t = false)]
+    pub no_report: bool,
 }
 
 
@@ -84,7 +315,18 @@ pub struct Config {
     pub icons: IconSet,
     pub colors: ColorSet,
     pub args: Args,
-
+    pub filter: Filter,
+    pub query: Option<Query>,
+    pub search: Option<Search>,
+    pub mounts: Mounts,
+    /// Only loaded with `--git` or `--git-changed-only`
+    pub git: Option<GitStatuses>,
+    /// The files listed by git rather than read from the file system, with `--tracked`, `--rev` or `--rev-diff`
+    pub tree: Option<GitTree>,
+    /// Only loaded with `--git-blame-summary`
+    pub history: Option<GitHistory>,
+    /// Only loaded with `--churn`
+    pub churn: Option<GitChurn>
 }
 
 fn load_glyphs(path: &Path) -> HashMap<String, String> {
@@ -118,6 +360,41 @@ fn get_data_dir_path() -> PathBuf {
 }
 
 
+fn get_clean_path(args: &Args) -> PathBuf {
+    let path_str = args.path.clone().unwrap_or(".".to_string());
+    let path = if Path::new(&path_str).is_absolute() {
+        PathBuf::from(path_str)
+    } else {
+        std::env::current_dir().unwrap().join(path_str)
+    }.canonicalize().unwrap();
+    let path = path.to_str().unwrap().strip_prefix(r"\\?\").unwrap_or(path.to_str().unwrap());
+    PathBuf::from_str(path).unwrap()
+}
+
+/// Reads the tree listed by git with `--tracked`, `--rev` or `--rev-diff`
+fn load_tree(args: &Args, root: &Path) -> Option<GitTree> {
+    let (tree, error) = if args.tracked {
+        (GitTree::load_index(root), "--tracked requires the path to be in a git repository".to_string())
+    } else if let Some(rev) = &args.rev {
+        (GitTree::load_revision(root, rev), format!("--rev requires a revision of the git repository containing the path, got '{}'", rev))
+    } else if let Some(range) = &args.rev_diff {
+        let Some((from, to)) = range.split_once("..") else {
+            Args::command().error(ErrorKind::ValueValidation, "--rev-diff requires a range of revisions, e.g. main..HEAD").exit()
+        };
+        // Like git, a missing end of the range stands for `HEAD`
+        let (from, to) = (if from.is_empty() { "HEAD" } else { from }, if to.is_empty() { "HEAD" } else { to });
+        (GitTree::load_diff(root, from, to), format!("--rev-diff requires revisions of the git repository containing the path, got '{}'", range))
+    } else {
+        return None;
+    };
+
+    match tree {
+        Ok(Some(tree)) => Some(tree),
+        Ok(None) => Args::command().error(ErrorKind::ValueValidation, error).exit(),
+        Err(e) => Args::command().error(ErrorKind::Io, format!("could not run git: {}", e)).exit()
+    }
+}
+
 /// A utility method that tries to find the **longest extension matching the filename**
 fn find_item_from_extension<'b>(map: &'b HashMap<String, String>, filename: &str) -> Option<&'b String> {
     if let Some(v) = map.get(filename) {
@@ -138,32 +415,72 @@ fn find_item_from_extension<'b>(map: &'b HashMap<String, String>, filename: &str
 
 impl Config {
     pub fn load() -> Self {
-        let data_dir = get_data_dir_path();
-        let data_dir = data_dir.as_path();
+        Config::new(Args::parse(), &get_data_dir_path())
+    }
+
+    /// Exits with the usage error when the git data or the patterns given by `args` can't be loaded
+    pub fn new(args: Args, data_dir: &Path) -> Self {
+        let root = get_clean_path(&args);
+        if args.watch && cfg!(not(target_os = "linux")) {
+            Args::command().error(ErrorKind::InvalidValue, "--watch is only available on Linux").exit();
+        }
+        let filter = Filter::new(&root, &args.include, &args.exclude, args.regex, args.ignore_case)
+            .unwrap_or_else(|e| Args::command().error(ErrorKind::ValueValidation, e).exit());
+        let query = args.query.as_ref().map(|q| Query::parse(q)
+            .unwrap_or_else(|e| Args::command().error(ErrorKind::ValueValidation, format!("invalid --where expression: {}", e)).exit()));
+        let search = args.contains.as_ref().map(|p| Search::new(p, args.regex, args.ignore_case)
+            .unwrap_or_else(|e| Args::command().error(ErrorKind::ValueValidation, e).exit()));
+        let git = (args.git || args.git_changed_only).then(|| match GitStatuses::load(&root) {
+            Ok(Some(git)) => git,
+            Ok(None) => Args::command().error(ErrorKind::ValueValidation, "--git requires the path to be in a git repository").exit(),
+            Err(e) => Args::command().error(ErrorKind::Io, format!("could not run git: {}", e)).exit()
+        });
+        let tree = load_tree(&args, &root);
+        let history = args.git_blame_summary.then(|| match GitHistory::load(&root, args.rev.as_deref()) {
+            Ok(Some(history)) => history,
+            Ok(None) => Args::command().error(ErrorKind::ValueValidation, "--git-blame-summary requires the path to be in a git repository").exit(),
+            Err(e) => Args::command().error(ErrorKind::Io, format!("could not run git: {}", e)).exit()
+        });
+        let churn = args.churn.as_ref().map(|since| match GitChurn::load(&root, since.as_deref(), args.rev.as_deref()) {
+            Ok(Some(churn)) => churn,
+            Ok(None) => Args::command().error(ErrorKind::ValueValidation, "--churn requires the path to be in a git repository").exit(),
+            Err(e) => Args::command().error(ErrorKind::Io, format!("could not run git: {}", e)).exit()
+        });
         Config {
             settings: load_settings(data_dir),
             glyphs: load_glyphs(data_dir),
             icons: load_icons(data_dir),
             colors: load_colors(data_dir),
-            args: Args::parse(),
+            args,
+            filter,
+            query,
+            search,
+            mounts: Mounts::load(),
+            git,
+            tree,
+            history,
+            churn
         }
     }
 
-    pub fn is_dir_ignored(&self, path: &PathBuf) -> bool {
+    pub fn is_dir_ignored(&self, path: &Path) -> bool {
         self.settings.ignored_dirs
             .iter()
             .any(|f| path.ends_with(f))
     }
 
+    pub fn is_past_max_depth(&self, depth: usize) -> bool {
+        self.args.level.is_some_and(|level| depth >= level)
+    }
+
+    pub fn get_threads_count(&self) -> usize {
+        self.args.threads
+            .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+            .unwrap_or(1)
+    }
+
     pub fn get_clean_current_path(&self) -> PathBuf {
-        let path_str = self.args.path.clone().unwrap_or(".".to_string());
-        let path = if Path::new(&path_str).is_absolute() {
-            PathBuf::from(path_str)
-        } else {
-            std::env::current_dir().unwrap().join(path_str)
-        }.canonicalize().unwrap();
-        let path = path.to_str().unwrap().strip_prefix(r"\\?\").unwrap_or(path.to_str().unwrap());
-        PathBuf::from_str(path).unwrap()
+        get_clean_path(&self.args)
     }
 
     pub fn get_associated_ext_glyph(&self, ext: &str) -> &String {
@@ -216,17 +533,109 @@ impl Config {
         self.colors.directories.wellknown.get(filename).unwrap_or(&self.colors.files.default)
     }
 
+    pub fn get_file_link_glyph(&self) -> &String {
+        self.glyphs.get(&self.icons.files.symlink).unwrap()
+    }
+
+    pub fn get_dir_link_glyph(&self) -> &String {
+        self.glyphs.get(&self.icons.directories.symlink).unwrap()
+    }
+
+    pub fn get_mount_glyph(&self) -> &String {
+        self.glyphs.get(&self.icons.directories.mount).unwrap()
+    }
+
+    pub fn get_repository_glyph(&self) -> &String {
+        self.glyphs.get(&self.icons.directories.repository).unwrap()
+    }
+
+    pub fn has_size_column(&self) -> bool {
+        self.args.size || self.args.long
+    }
+
+    pub fn has_mtime_column(&self) -> bool {
+        self.args.mtime || self.args.long
+    }
+
+    pub fn has_perms_column(&self) -> bool {
+        self.args.perms || self.args.long
+    }
+
+    pub fn has_owner_column(&self) -> bool {
+        self.args.owner || self.args.long
+    }
+
+    /// Whether the last commit of each entry is shown, with `--git-blame-summary`
+    pub fn has_commit_column(&self) -> bool {
+        self.args.git_blame_summary
+    }
+
+    /// Whether the metadata of the entries has to be read for the columns
+    pub fn has_columns(&self) -> bool {
+        self.has_size_column() || self.has_mtime_column() || self.has_perms_column() || self.has_owner_column()
+            || self.has_commit_column()
+    }
+
     pub fn is_filtered(&self) -> bool {
-        self.args.filter.is_some()
+        self.filter.is_active() || self.query.is_some() || self.search.is_some() || self.args.git_changed_only
     }
 
-    pub fn is_file_valid(&self, path: &Path) -> bool {
-        let r = match &self.args.filter {
-            Some(gm) => glob_match(gm, path.file_name().unwrap().to_str().unwrap()),
-            _ => true
-        };
-        // println!("{} -> {}", path.display(), r);
-        r
+    pub fn is_pruned(&self) -> bool {
+        self.args.prune || (self.is_filtered() && !self.args.no_prune)
+    }
+
+    /// `meta` is the metadata already read, if any, which is all there is for the entries of a revision
+    pub fn is_file_valid(&self, path: &Path, depth: usize, meta: Option<&EntryMeta>) -> bool {
+        !self.filter.is_excluded(path)
+            && self.filter.is_included(path)
+            && self.is_query_match(path, depth, meta)
+            && (!self.args.git_changed_only || self.get_git_file_status(path).is_some_and(|s| s.is_changed()))
+    }
+
+    /// Directories are shown when they have matching content, when matched by `--where` themselves,
+    /// or when they have changes that can't be shown, like deleted files
+    pub fn is_dir_selected(&self, path: &Path, depth: usize, meta: Option<&EntryMeta>) -> bool {
+        (self.query.is_some() && self.is_file_valid(path, depth, meta))
+            || (self.args.git_changed_only && self.get_git_dir_status(path).is_some_and(|s| s.is_changed()))
+    }
+
+    pub fn get_git_file_status(&self, path: &Path) -> Option<GitStatus> {
+        self.git.as_ref().and_then(|g| g.get_file_status(path))
+    }
+
+    pub fn get_git_dir_status(&self, path: &Path) -> Option<GitStatus> {
+        self.git.as_ref().and_then(|g| g.get_dir_status(path))
+    }
+
+    pub fn get_last_file_commit(&self, path: &Path) -> Option<&LastCommit> {
+        self.history.as_ref().and_then(|h| h.get_file_commit(path))
+    }
+
+    pub fn get_last_dir_commit(&self, path: &Path) -> Option<&LastCommit> {
+        self.history.as_ref().and_then(|h| h.get_dir_commit(path))
+    }
+
+    pub fn get_file_churn(&self, path: &Path) -> Option<Churn> {
+        self.churn.as_ref().map(|c| c.get_file_churn(path))
+    }
+
+    pub fn get_dir_churn(&self, path: &Path) -> Option<Churn> {
+        self.churn.as_ref().map(|c| c.get_dir_churn(path))
+    }
+
+    pub fn get_git_change(&self, path: &Path) -> Option<GitChange> {
+        self.tree.as_ref().and_then(|t| t.get_change(path))
+    }
+
+    fn is_query_match(&self, path: &Path, depth: usize, meta: Option<&EntryMeta>) -> bool {
+        match &self.query {
+            Some(query) => {
+                // Entries are one level deeper than the directory listing them, the root's being at depth 1
+                let relative_path = self.filter.get_relative_path(path);
+                query.is_match(&Entry::new(path, &relative_path, depth + 1, meta))
+            },
+            None => true
+        }
     }
 
 }
\ No newline at end of file
@@ -0,0 +1,75 @@
+use std::cmp::Ordering;
+use std::path::Path;
+
+/// Compares names the way a human would: case insensitive, numbers by value, `file2` before `file10`
+pub fn compare_natural(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(ca), Some(cb)) if ca.is_ascii_digit() && cb.is_ascii_digit() => {
+                let na = take_number(&mut a_chars);
+                let nb = take_number(&mut b_chars);
+                let ordering = compare_numbers(&na, &nb);
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            },
+            (Some(ca), Some(cb)) => {
+                let ordering = ca.to_lowercase().cmp(cb.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        number.push(c);
+    }
+    number
+}
+
+/// Compares digit strings by value without overflowing, whatever their length
+fn compare_numbers(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+/// Groups names by extension, the ones without any coming first
+pub fn compare_extension(a: &Path, b: &Path) -> Ordering {
+    a.extension().cmp(&b.extension())
+        .then_with(|| a.file_name().cmp(&b.file_name()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_natural() {
+        let mut names = vec!["file10.txt", "File2.txt", "file1.txt", "file02b.txt", "a", "file"];
+        names.sort_by(|a, b| compare_natural(a, b));
+        assert_eq!(names, vec!["a", "file", "file1.txt", "File2.txt", "file02b.txt", "file10.txt"]);
+
+        assert_eq!(compare_natural("v1.10.0", "v1.9.3"), Ordering::Greater);
+        assert_eq!(compare_natural("123456789012345678901234567890", "99"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_compare_extension() {
+        let mut names = vec![Path::new("b.rs"), Path::new("a.toml"), Path::new("Makefile"), Path::new("a.rs")];
+        names.sort_by(|a, b| compare_extension(a, b));
+        assert_eq!(names, vec![Path::new("Makefile"), Path::new("a.rs"), Path::new("b.rs"), Path::new("a.toml")]);
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask, Watches};
+use crate::render_tree;
+use crate::settings::Config;
+
+/// Time without any change before the tree is rendered again
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(200);
+/// Longest time changes that keep coming can put off the rendering, e.g. during builds
+const MAX_DEBOUNCE_DELAY: Duration = Duration::from_secs(2);
+/// How long the entries that changed stay highlighted
+const HIGHLIGHT_DURATION: Duration = Duration::from_secs(2);
+
+/// The directory an event comes from, and the name of the entry that changed in it
+type Change = (WatchDescriptor, Option<OsString>);
+
+/// Renders the tree again whenever one of the directories it was read from changes, with `--watch`
+pub fn run(config: Config) -> ExitCode {
+    let inotify = match Inotify::init() {
+        Ok(inotify) => inotify,
+        Err(error) => {
+            eprintln!("could not watch the tree: {}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+    let watches = inotify.watches();
+    let rx_changes = read_changes(inotify);
+
+    let mut config = config;
+    let mut watched: HashMap<PathBuf, WatchDescriptor> = HashMap::new();
+    let mut highlighted: HashSet<PathBuf> = HashSet::new();
+    loop {
+        // Clears the screen along with its scrollback
+        print!("\x1B[2J\x1B[3J\x1B[H");
+        io::stdout().flush().unwrap();
+        let path = config.get_clean_current_path();
+        let is_highlighting = !highlighted.is_empty();
+        // Directories are watched before they're read, so that the changes made while rendering aren't missed
+        let read = Mutex::new(HashMap::new());
+        let watch_dir = |dir: &Path| {
+            if let Some(wd) = watch(&watches, dir) {
+                read.lock().unwrap().insert(dir.to_path_buf(), wd);
+            }
+        };
+        render_tree(&path, &config, std::mem::take(&mut highlighted), &watch_dir);
+        let read = read.into_inner().unwrap();
+        unwatch_unread(&watches, &watched, &read);
+        watched = read;
+
+        // The highlights are cleared by rendering the tree again once they've been seen
+        let dirs: HashMap<&WatchDescriptor, &PathBuf> = watched.iter().map(|(p, wd)| (wd, p)).collect();
+        let resolve = |(wd, name): Change| dirs.get(&wd).map(|d| name.map_or(d.to_path_buf(), |n| d.join(n)));
+        match wait_for_changes(&rx_changes, is_highlighting.then_some(HIGHLIGHT_DURATION), resolve) {
+            Some(changed) => highlighted = changed,
+            None => {
+                eprintln!("could not watch the tree anymore");
+                return ExitCode::FAILURE;
+            }
+        }
+
+        // The git statuses, index and history are read again along with the tree
+        config = Config::load();
+    }
+}
+
+/// Reads the events on a thread of their own, until they can't be read anymore
+fn read_changes(mut inotify: Inotify) -> Receiver<Change> {
+    let (tx_changes, rx_changes) = crossbeam_channel::unbounded();
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        while let Ok(events) = inotify.read_events_blocking(&mut buffer) {
+            // Removed watches send a last event, overflows aren't tied to any watch and are left to resolve
+            for event in events.filter(|e| !e.mask.contains(EventMask::IGNORED)) {
+                if tx_changes.send((event.wd, event.name.map(|n| n.to_os_string()))).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    rx_changes
+}
+
+/// Watching a directory again gives back its descriptor, a new one when it was replaced
+fn watch(watches: &Watches, dir: &Path) -> Option<WatchDescriptor> {
+    let mask = WatchMask::CREATE | WatchMask::DELETE | WatchMask::MODIFY | WatchMask::MOVE
+        | WatchMask::DELETE_SELF | WatchMask::MOVE_SELF | WatchMask::ONLYDIR;
+    watches.clone().add(dir, mask).ok()
+}
+
+/// Stops watching the directories of the previous frame that weren't read again
+fn unwatch_unread(watches: &Watches, previous: &HashMap<PathBuf, WatchDescriptor>, read: &HashMap<PathBuf, WatchDescriptor>) {
+    let read_wds: HashSet<&WatchDescriptor> = read.values().collect();
+    let mut watches = watches.clone();
+    for (_, wd) in previous.iter().filter(|(dir, wd)| !read.contains_key(*dir) && !read_wds.contains(wd)) {
+        // Fails for the removed directories, whose watches are already gone
+        let _ = watches.remove(wd.clone());
+    }
+}
+
+/// Waits for a change then for the changes to settle, and returns the entries that changed. Gives up
+/// after `timeout` without any change, `None` once the changes can't be read anymore
+fn wait_for_changes<T>(rx_changes: &Receiver<T>, timeout: Option<Duration>, resolve: impl Fn(T) -> Option<PathBuf>) -> Option<HashSet<PathBuf>> {
+    let mut changed = HashSet::new();
+    let first = match timeout {
+        Some(timeout) => match rx_changes.recv_timeout(timeout) {
+            Ok(change) => change,
+            Err(RecvTimeoutError::Timeout) => return Some(changed),
+            Err(RecvTimeoutError::Disconnected) => return None
+        },
+        None => rx_changes.recv().ok()?
+    };
+    changed.extend(resolve(first));
+
+    let start = Instant::now();
+    while start.elapsed() < MAX_DEBOUNCE_DELAY {
+        match rx_changes.recv_timeout(DEBOUNCE_DELAY) {
+            Ok(change) => changed.extend(resolve(change)),
+            Err(RecvTimeoutError::Timeout) => break,
+            Err(RecvTimeoutError::Disconnected) => return None
+        }
+    }
+    Some(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_for_changes() {
+        let (tx_changes, rx_changes) = crossbeam_channel::unbounded();
+        let resolve = |name: &str| Some(PathBuf::from("/root").join(name)).filter(|_| name != "unknown");
+
+        let changed = wait_for_changes(&rx_changes, Some(Duration::from_millis(10)), resolve);
+        assert_eq!(changed, Some(HashSet::new()));
+
+        for name in ["a.rs", "b.rs", "a.rs", "unknown"] {
+            tx_changes.send(name).unwrap();
+        }
+        let changed = wait_for_changes(&rx_changes, None, resolve).unwrap();
+        assert_eq!(changed, HashSet::from([PathBuf::from("/root/a.rs"), PathBuf::from("/root/b.rs")]));
+
+        drop(tx_changes);
+        assert_eq!(wait_for_changes(&rx_changes, None, resolve), None);
+    }
+}